@@ -1,14 +1,71 @@
-use core::{marker::PhantomData, u8};
+use core::{cell::Cell, marker::PhantomData, time::Duration};
 
-use alloc::boxed::Box;
-use da14531_sdk::app_modules::timer::AppTimer;
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use rtt_target::{rprint, rprintln};
 
-/// Stop advertising and go to hibernation after X secs
-const APP_ADVERTISTE_STOP_TIMER: u32 = 60;
+use self::{
+    adc::{AdcChannel, AdcSettings},
+    auth::{AuthBlock, DeviceKey, AUTH_BLOCK_LEN},
+    battery::{BatteryType, BATTERY_SAMPLE_INTERVAL},
+    config::{
+        AdvertisingConfig, CalibrationCommand, ConfigError, IdleConfig, PostWriteConfig,
+        PostWritePolicy, PostWriteTarget, TemperatureCalibration, TemperatureConfig,
+        TemperatureThresholds,
+    },
+    conn_params::{ConnectionParams, PreferredConnectionParams},
+    connection::{
+        Connection, ConnectionTable, ConnectionTimers, Notification, APP_MAX_CONNECTIONS,
+    },
+    event::{AppEvent, EventSink},
+    filter::Filter,
+    history::{History, HistoryCommand, HistorySample, HISTORY_CHUNK_LEN, HISTORY_LEN},
+    led::{LedEventPatterns, LedPattern},
+    persistent::PersistentSettings,
+    retained::RetainedState,
+    sensor::{encode_sensor_value, SensorError, SENSORS, SENSOR_COUNT, SENSOR_SAMPLE_INTERVAL},
+    state::{AppState, RejectedTransition, TransitionHook},
+    supply::{SupplyStatus, SupplyThresholds},
+    temperature::{check_temperature, encode_temperature, TemperatureError, TemperatureLevel},
+    unlock::{FailedAttempts, UnlockCredential, UnlockStatus, UNLOCK_MAX_ATTEMPTS_PER_CONNECTION},
+};
+
+/// Fixed-point conversion of GPADC samples
+pub mod adc;
+/// AES-128 challenge-response authentication
+pub mod auth;
+/// Battery types and their discharge curves
+pub mod battery;
+/// Settings which can be changed by a central
+pub mod config;
+/// Negotiation of the connection parameters
+pub mod conn_params;
+/// Table of the connected centrals
+pub mod connection;
+/// Events passed from SDK callbacks and interrupts to the app
+pub mod event;
+/// Filters for streams of ADC samples
+pub mod filter;
+/// Ring buffer of past temperature samples
+pub mod history;
+/// LED patterns and the player stepping through them
+pub mod led;
+/// Data which is kept across power loss
+pub mod persistent;
+/// Data which is kept across hibernation
+pub mod retained;
+/// External analog sensors and their conversion curves
+pub mod sensor;
+/// Application states and the transition table between them
+pub mod state;
+/// Under-voltage detection of the battery and core supply
+pub mod supply;
+/// Range check and encoding of the die temperature
+pub mod temperature;
+/// Credential check of the unlock characteristic
+pub mod unlock;
 
 /// Type of sound to play
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Sound {
     Connected,
     Disconnected,
@@ -17,6 +74,13 @@ pub enum Sound {
     Alarm,
 }
 
+impl Sound {
+    /// Check if the sound draws too much current for a marginal supply
+    pub fn is_power_hungry(&self) -> bool {
+        matches!(self, Sound::Alarm)
+    }
+}
+
 /// Defines an interface to access the peripherals
 pub trait PeripheralsDriver {
     fn new() -> Self;
@@ -27,49 +91,217 @@ pub trait PeripheralsDriver {
         finish_callback: Option<Box<dyn FnOnce()>>,
     );
     fn start_hibernation(&mut self);
-    fn get_temperature(&self) -> u16;
+    /// Take a 16 bit GPADC sample with `settings`, passed through the filter of its channel
+    ///
+    /// Returns `None` if the channel is a pin, which is not set up as analog input.
+    fn sample_adc(&self, settings: &AdcSettings) -> Option<u16>;
+    /// Set the filter pipeline of `channel`, `None` disables filtering
+    fn set_adc_filter(&mut self, channel: AdcChannel, filter: Option<Box<dyn Filter>>);
+    /// Sample of the die temperature sensor at 25 °C (16 bit)
+    fn temperature_reference(&self) -> u16 {
+        adc::TEMP_SAMPLE_AT_25C
+    }
+    /// Voltage at the GPADC input in mV, `None` like `sample_adc`
+    fn read_adc(&self, settings: &AdcSettings) -> Option<u16> {
+        self.sample_adc(settings)
+            .map(|sample| settings.millivolts(sample))
+    }
+    /// Die temperature in milli °C
+    fn get_temperature(&self) -> Result<i32, TemperatureError> {
+        let sample = self
+            .sample_adc(&AdcSettings::TEMPERATURE)
+            .ok_or(TemperatureError::Unavailable)?;
+        check_temperature(adc::millicelsius(sample, self.temperature_reference()))
+    }
+    /// Battery voltage in mV
+    fn get_battery_voltage(&self) -> u16 {
+        // Internal channels are always available
+        self.read_adc(&self.battery_type().adc_settings())
+            .unwrap_or_default()
+    }
+    fn battery_type(&self) -> BatteryType;
+    /// Core supply voltage (VDDD) in mV
+    fn get_vddd_voltage(&self) -> u16 {
+        self.read_adc(&AdcSettings::VDDD).unwrap_or_default()
+    }
     fn feed_watchdog(&mut self);
     fn set_led(&mut self, state: bool);
+    /// Show `pattern` on the LED, replaces the running one
+    fn play_led_pattern(&mut self, pattern: LedPattern);
+    /// Advance the LED pattern (`AppEvent::LedTimer`)
+    fn on_led_timer(&mut self);
+    fn on_pwm_interrupt(&mut self);
+    fn store_retained(&mut self, state: &RetainedState);
+    fn load_retained(&mut self) -> Option<RetainedState>;
+    /// Write settings to storage, which keeps them without power
+    fn store_persistent(&mut self, settings: &PersistentSettings);
+    fn load_persistent(&mut self) -> Option<PersistentSettings>;
+    /// Read the key provisioned for this device, `None` if there is none
+    fn load_device_key(&mut self) -> Option<DeviceKey>;
+    /// Read the unlock credential provisioned for this device, `None` if there is none
+    fn load_unlock_credential(&mut self) -> Option<UnlockCredential>;
+    fn random_bytes(&mut self, buffer: &mut [u8]);
+    fn aes128_encrypt(&mut self, key: &AuthBlock, block: &AuthBlock) -> Option<AuthBlock>;
 }
 
 /// Defines an interface to control the BLE stack
 pub trait BleDriver {
-    fn start_adverstising();
+    /// Advertise for `period`, the stack reports the end as `AppEvent::AdvertisingStopped`
+    fn start_adverstising(period: Duration);
     fn stop_adverstising();
-    fn disconnect(connection_handle: u32);
+    fn disconnect(conidx: u8);
+    /// Ask the central to update the connection parameters
+    fn request_connection_params(conidx: u8, params: &PreferredConnectionParams);
+    /// Send `value` of the `notification` characteristic to connection `conidx`
+    fn notify(conidx: u8, notification: Notification, value: &[u8]);
+}
+
+/// Defines an interface to one-shot timers
+pub trait TimerDriver: Sized {
+    /// Call `callback` once after `delay`, `None` if no timer is available
+    fn create(delay: Duration, callback: Box<dyn Fn()>) -> Option<Self>;
+    /// Stop the timer without calling the callback
+    fn cancel(self);
+    /// Restart the timer with a new delay, `None` if no timer is available
+    fn reschedule(self, delay: Duration) -> Option<Self>;
+    /// Get the time since an arbitrary, fixed point in the past
+    fn now() -> Duration;
+    /// Get the time from `earlier` to `later` (both from `now`), `now` may wrap around in between
+    fn elapsed(earlier: Duration, later: Duration) -> Duration {
+        later.saturating_sub(earlier)
+    }
 }
 
 /// Holds the state of the application
-pub struct App<P, BLE>
+pub struct App<P, BLE, T>
 where
     Self: 'static,
     P: 'static + PeripheralsDriver,
     BLE: 'static + BleDriver,
+    T: 'static + TimerDriver,
 {
-    ///
-    hibernation_timer: Option<AppTimer>,
+    /// Timer which stops advertising, which leads into hibernation, with a flag set once it
+    /// expired
+    hibernation_timer: Option<(T, Rc<Cell<bool>>)>,
+    adv_config: AdvertisingConfig,
+    /// An advertising period is running (the stack ends it on its own, when a central connects)
+    advertising: bool,
     peripherals: Option<P>,
-    alarm_on: bool,
-    connection_handle: Option<u32>,
-    led_state: bool,
+    state: AppState,
+    transition_hook: Option<TransitionHook>,
+    connections: ConnectionTable<APP_MAX_CONNECTIONS>,
+    preferred_conn_params: PreferredConnectionParams,
+    /// Timers, which send the next connection parameter request of a connection
+    conn_param_timers: ConnectionTimers<T>,
+    post_write_config: PostWriteConfig,
+    idle_config: IdleConfig,
+    /// Timers, which disconnect an inactive connection
+    idle_timers: ConnectionTimers<T>,
+    /// Pattern written by a central (`Off` included), `None` leaves the LED to the event patterns
+    led_pattern: Option<LedPattern>,
+    led_event_patterns: LedEventPatterns,
+    /// Credential of the unlock characteristic, `None` until one is provisioned
+    unlock_credential: Option<UnlockCredential>,
+    failed_unlock_attempts: FailedAttempts,
+    /// Timer, which ends the lockout after a failed unlock attempt, with a flag set once it
+    /// expired
+    unlock_lockout_timer: Option<(T, Rc<Cell<bool>>)>,
+    /// Key of the challenge-response authentication, `None` until one is provisioned
+    device_key: Option<DeviceKey>,
+    /// Queues events raised by timers
+    event_sink: Option<EventSink>,
+    /// Timer, which triggers the next battery measurement
+    battery_timer: Option<T>,
+    /// Battery level in % of the last measurement
+    battery_level: Option<u8>,
+    /// Thresholds set by `set_supply_thresholds`, the battery's defaults otherwise
+    supply_thresholds: Option<SupplyThresholds>,
+    /// Result of the last supply measurement
+    supply_status: Option<SupplyStatus>,
+    temperature_config: TemperatureConfig,
+    temperature_calibration: TemperatureCalibration,
+    temperature_thresholds: TemperatureThresholds,
+    /// Level of the last valid sample relative to the thresholds
+    temperature_level: TemperatureLevel,
+    /// Timer, which triggers the next temperature sample, with a flag set once it expired
+    temperature_timer: Option<(T, Rc<Cell<bool>>)>,
+    /// Die temperature in milli °C of the last sample
+    temperature: Option<Result<i32, TemperatureError>>,
+    /// Temperature sent with the last notification
+    notified_temperature: Option<i32>,
+    history: History<HISTORY_LEN>,
+    /// Time since boot, advanced by every temperature sample, which is less than one wrap of
+    /// `TimerDriver::now` apart
+    uptime: Duration,
+    /// `TimerDriver::now` at the last advance of `uptime`
+    uptime_now: Option<Duration>,
+    /// Timer, which triggers the next sample of the external sensors
+    sensor_timer: Option<T>,
+    /// Value of the last sample of each external sensor
+    sensor_values: [Option<Result<i32, SensorError>>; SENSOR_COUNT],
+    /// Value of each external sensor sent with the last notification
+    notified_sensor_values: [Option<i32>; SENSOR_COUNT],
     _ble: PhantomData<BLE>,
 }
 
+impl<P, BLE, T> Default for App<P, BLE, T>
+where
+    P: PeripheralsDriver,
+    BLE: BleDriver,
+    T: TimerDriver,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Business logic of the application
-impl<P, BLE> App<P, BLE>
+impl<P, BLE, T> App<P, BLE, T>
 where
     P: PeripheralsDriver,
     BLE: BleDriver,
+    T: TimerDriver,
 {
     /// Create new instance of App
     pub const fn new() -> Self {
         Self {
             hibernation_timer: None,
+            adv_config: AdvertisingConfig::DEFAULT,
+            advertising: false,
             peripherals: None,
             _ble: PhantomData,
-            alarm_on: false,
-            connection_handle: None,
-            led_state: false,
+            state: AppState::Idle,
+            transition_hook: None,
+            connections: ConnectionTable::new(),
+            preferred_conn_params: PreferredConnectionParams::DEFAULT,
+            conn_param_timers: ConnectionTimers::new(),
+            post_write_config: PostWriteConfig::DEFAULT,
+            idle_config: IdleConfig::DEFAULT,
+            idle_timers: ConnectionTimers::new(),
+            led_pattern: None,
+            led_event_patterns: LedEventPatterns::DEFAULT,
+            unlock_credential: None,
+            failed_unlock_attempts: FailedAttempts::new(),
+            unlock_lockout_timer: None,
+            device_key: None,
+            event_sink: None,
+            battery_timer: None,
+            battery_level: None,
+            supply_thresholds: None,
+            supply_status: None,
+            temperature_config: TemperatureConfig::DEFAULT,
+            temperature_calibration: TemperatureCalibration::FACTORY,
+            temperature_thresholds: TemperatureThresholds::DEFAULT,
+            temperature_level: TemperatureLevel::Normal,
+            temperature_timer: None,
+            temperature: None,
+            notified_temperature: None,
+            history: History::new(),
+            uptime: Duration::ZERO,
+            uptime_now: None,
+            sensor_timer: None,
+            sensor_values: [None; SENSOR_COUNT],
+            notified_sensor_values: [None; SENSOR_COUNT],
         }
     }
 
@@ -80,88 +312,1143 @@ where
         self.peripherals = Some(P::new());
 
         rprintln!("done!");
+
+        self.device_key = self.peripherals().load_device_key();
+        if self.device_key.is_none() {
+            rprintln!("No device key provisioned, authentication is impossible");
+        }
+
+        self.unlock_credential = self.peripherals().load_unlock_credential();
+        if self.unlock_credential.is_none() {
+            rprintln!("No unlock credential provisioned, unlocking is impossible");
+        }
+
+        if let Some(persistent) = self.peripherals().load_persistent() {
+            if persistent.is_valid() {
+                rprintln!("Restored {:?}", persistent);
+                self.temperature_calibration = persistent.temperature_calibration;
+            }
+        }
+
+        if let Some(retained) = self.peripherals().load_retained() {
+            if retained.is_valid() {
+                rprintln!("Restored {:?}", retained);
+                self.adv_config = retained.adv_config;
+                self.failed_unlock_attempts =
+                    FailedAttempts::with_total(retained.failed_unlock_attempts);
+                self.temperature_thresholds = retained.temperature_thresholds;
+            }
+        }
+    }
+
+    /// Set the function, which queues events raised by timers
+    pub fn set_event_sink(&mut self, sink: EventSink) {
+        self.event_sink = Some(sink);
+    }
+
+    /// Create a timer, which raises `event` after `delay`, `None` without an event sink
+    fn start_event_timer(&self, delay: Duration, event: AppEvent) -> Option<T> {
+        let sink = self.event_sink?;
+        T::create(delay, Box::new(move || sink(event)))
     }
 
-    /// Start timer that stops advertisement after `APP_ADVERTISTE_STOP_TIMER` seconds
+    /// Dispatch an event from the event queue to its handler
+    pub fn handle_event(&mut self, event: AppEvent) {
+        // PWM and LED steps come every few ms, logging them would flood RTT and stretch the
+        // handling
+        if !matches!(event, AppEvent::PwmInterrupt | AppEvent::LedTimer) {
+            rprintln!("App::handle_event({:?})", event);
+        }
+
+        match event {
+            AppEvent::StartAdvertising => self.on_start_advertising(),
+            AppEvent::AdvertisingStopped => self.on_advertising_stopped(),
+            AppEvent::StartHibernation => self.on_start_hibernation(),
+            AppEvent::Connect(conidx) => self.on_connect(conidx),
+            AppEvent::Disconnect(conidx) => self.on_disconnect(conidx),
+            AppEvent::ConnParamsUpdated(conidx, params) => {
+                self.on_conn_params_updated(conidx, params)
+            }
+            AppEvent::ConnParamsRejected => self.on_conn_params_rejected(),
+            AppEvent::Alarm => self.on_alarm(),
+            AppEvent::PwmInterrupt => self.peripherals().on_pwm_interrupt(),
+            AppEvent::LedTimer => self.peripherals().on_led_timer(),
+            AppEvent::BatteryMeasure => self.on_battery_measure(),
+            AppEvent::TemperatureMeasure => self.on_temperature_measure(),
+            AppEvent::SensorMeasure => self.on_sensor_measure(),
+            AppEvent::StorePersistent => self.on_store_persistent(),
+        }
+    }
+
+    /// Get the current state
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+
+    /// Set a hook, which is called on every accepted and rejected transition
+    pub fn set_transition_hook(&mut self, hook: Option<TransitionHook>) {
+        self.transition_hook = hook;
+    }
+
+    /// Move to state `to` if the transition table allows it
+    ///
+    /// Staying in the current state is always accepted and does not call the hook.
+    pub fn transition(&mut self, to: AppState) -> Result<(), RejectedTransition> {
+        let from = self.state;
+
+        if from == to {
+            return Ok(());
+        }
+
+        let accepted = from.can_transition_to(to);
+
+        if let Some(hook) = self.transition_hook {
+            hook(from, to, accepted);
+        }
+
+        if accepted {
+            rprintln!("App: {:?} -> {:?}", from, to);
+            self.state = to;
+            Ok(())
+        } else {
+            rprintln!("App: Rejected transition {:?} -> {:?}", from, to);
+            Err(RejectedTransition { from, to })
+        }
+    }
+
+    /// Start timer that stops advertisement after the configured timeout
     fn start_hibernation_timer(&mut self) {
         if self.hibernation_timer.is_none() {
-            self.hibernation_timer = AppTimer::new(
-                APP_ADVERTISTE_STOP_TIMER * 100,
-                Box::new(|| {
+            let expired = Rc::new(Cell::new(false));
+            let timer_expired = expired.clone();
+            self.hibernation_timer = T::create(
+                self.adv_config.timeout(),
+                Box::new(move || {
+                    timer_expired.set(true);
                     BLE::stop_adverstising();
-                    // self.on_init_hibernation();
                 }),
             )
+            .map(|timer| (timer, expired));
         }
     }
 
     /// Cancel hibernation timer
     fn cancel_hibernation_timer(&mut self) {
-        if let Some(timer) = self.hibernation_timer.take() {
-            timer.cancel();
+        if let Some((timer, expired)) = self.hibernation_timer.take() {
+            // The timer already expired, so it must not be cancelled
+            if !expired.get() {
+                timer.cancel();
+            }
         }
     }
 
+    /// Get the advertising config
+    pub fn advertising_config(&self) -> AdvertisingConfig {
+        self.adv_config
+    }
+
+    /// Set the advertising config, which is used from the next `on_start_advertising` on
+    pub fn on_set_advertising_config(&mut self, config: AdvertisingConfig) {
+        rprintln!("App::on_set_advertising_config({:?})", config);
+        self.adv_config = config;
+    }
+
     /// Start advertising handler
+    ///
+    /// While centrals are connected or the alarm plays, advertising continues as long as slots
+    /// are free.
     pub fn on_start_advertising(&mut self) {
         rprintln!("App::on_start_advertising()");
 
+        if matches!(self.state, AppState::Connected | AppState::Alarm) {
+            self.resume_advertising();
+            return;
+        }
+
+        if self.transition(AppState::Advertising).is_err() {
+            return;
+        }
+
         self.start_hibernation_timer();
+        self.resume_advertising();
+    }
+
+    /// Advertising stopped handler, either a period ended or the hibernation timer expired
+    pub fn on_advertising_stopped(&mut self) {
+        self.advertising = false;
 
-        BLE::start_adverstising();
+        let timed_out = match &self.hibernation_timer {
+            Some((_, expired)) => expired.get(),
+            None => true,
+        };
+
+        if matches!(self.state, AppState::Connected | AppState::Alarm) {
+            self.resume_advertising();
+        } else if !timed_out && self.state == AppState::Advertising {
+            self.on_start_advertising();
+        } else {
+            self.on_start_hibernation();
+        }
+    }
+
+    /// Advertise for another period, if none is running and a further central can connect
+    fn resume_advertising(&mut self) {
+        if !self.advertising && !self.connections.is_full() {
+            self.advertising = true;
+            BLE::start_adverstising(self.adv_config.period());
+        }
     }
 
-    /// Start hibernation handler
+    /// Start hibernation handler, the device keeps advertising instead while the alarm is on or
+    /// the temperature history is recorded
     pub fn on_start_hibernation(&mut self) {
         rprintln!("App::on_start_hibernation()");
+
+        // Hibernating would silence the alarm (a central has to be able to connect and unlock)
+        // and lose the history in RAM
+        if self.is_alarm_on() || self.temperature_config.history_interval().is_some() {
+            self.cancel_hibernation_timer();
+            self.resume_advertising();
+            return;
+        }
+
+        if self.transition(AppState::Hibernating).is_err() {
+            return;
+        }
+
+        self.cancel_hibernation_timer();
+        self.update_led();
+
+        self.store_retained();
         self.peripherals().start_hibernation();
     }
 
-    /// Set LED
+    /// Save the state, which has to survive hibernation and resets
+    fn store_retained(&mut self) {
+        let retained = RetainedState {
+            adv_config: self.adv_config,
+            failed_unlock_attempts: self.failed_unlock_attempts.total(),
+            temperature_thresholds: self.temperature_thresholds,
+        };
+        self.peripherals().store_retained(&retained);
+    }
+
+    /// Set LED, the state replaces the event patterns (except the alarm) until a pattern is
+    /// selected
     pub fn on_set_led(&mut self, state: bool) {
-        self.peripherals().set_led(state);
-        self.led_state = state;
+        rprintln!("App::on_set_led({})", state);
+        self.led_pattern = Some(if state {
+            LedPattern::On
+        } else {
+            LedPattern::Off
+        });
+        self.update_led();
     }
 
-    /// Get state of the LED
+    /// Get state of the LED (on if any pattern is shown)
     pub fn get_led_state(&mut self) -> bool {
-        self.led_state
+        self.led_pattern() != LedPattern::Off
     }
 
-    /// Get the die temperature
-    pub fn get_temperature(&mut self) -> u16 {
-        self.peripherals().get_temperature()
+    /// Get the pattern shown on the LED
+    pub fn led_pattern(&self) -> LedPattern {
+        match (self.state, self.led_pattern) {
+            (AppState::Alarm, _) => self.led_event_patterns.alarm,
+            // Timers stop in hibernation, only a steady LED can be kept
+            (AppState::Hibernating, Some(LedPattern::On)) => LedPattern::On,
+            (AppState::Hibernating, _) => LedPattern::Off,
+            (_, Some(pattern)) => pattern,
+            (AppState::Connected, None) => self.led_event_patterns.connect,
+            (AppState::Idle | AppState::Advertising, None) => self.led_event_patterns.disconnect,
+        }
     }
 
-    /// Connect event handler
-    pub fn on_connect(&mut self, connection_handle: Option<u32>) {
-        self.connection_handle = connection_handle;
+    /// LED pattern selection handler, the pattern replaces the event patterns (except the alarm)
+    /// and `Off` restores them
+    pub fn on_set_led_pattern(&mut self, pattern: LedPattern) {
+        rprintln!("App::on_set_led_pattern({:?})", pattern);
+        self.led_pattern = match pattern {
+            LedPattern::Off => None,
+            pattern => Some(pattern),
+        };
+        self.update_led();
+    }
 
-        if connection_handle.is_some() {
-            self.cancel_hibernation_timer();
-            self.peripherals
-                .as_mut()
-                .unwrap()
-                .play_sound(Sound::Connected, false, None);
+    pub fn led_event_patterns(&self) -> LedEventPatterns {
+        self.led_event_patterns
+    }
+
+    /// Set the patterns shown on app events, they are used from the next event on
+    pub fn set_led_event_patterns(&mut self, patterns: LedEventPatterns) {
+        self.led_event_patterns = patterns;
+    }
+
+    /// Show the pattern of the current state, the alarm overrides everything and a pattern
+    /// written by a central overrides the connect and disconnect patterns
+    fn update_led(&mut self) {
+        let pattern = self.led_pattern();
+        self.peripherals().play_led_pattern(pattern);
+    }
+
+    /// Get the die temperature in milli °C of the last sample
+    pub fn get_temperature(&mut self) -> Result<i32, TemperatureError> {
+        match self.temperature {
+            Some(temperature) => temperature,
+            None => self.measure_temperature(),
+        }
+    }
+
+    fn measure_temperature(&mut self) -> Result<i32, TemperatureError> {
+        let calibration = self.temperature_calibration;
+        let temperature = self
+            .peripherals()
+            .get_temperature()
+            .and_then(|reading| check_temperature(calibration.apply(reading)));
+        if let Err(error) = temperature {
+            rprintln!("App: Temperature rejected {:?}", error);
+        }
+
+        self.temperature = Some(temperature);
+        temperature
+    }
+
+    /// Temperature sampling handler, notifies subscribed centrals if the temperature changed by
+    /// more than the configured delta since the last notification
+    pub fn on_temperature_measure(&mut self) {
+        // The timer already expired, so it must not be cancelled
+        self.temperature_timer = None;
+        self.advance_uptime();
+
+        // A rejected reading is not notified, subscribers keep the last valid temperature
+        if let Ok(temperature) = self.measure_temperature() {
+            self.record_history(temperature);
+            self.check_temperature_thresholds(temperature);
+
+            let changed = match self.notified_temperature {
+                Some(notified) => {
+                    (temperature - notified).unsigned_abs() > self.temperature_config.delta() as u32
+                }
+                None => true,
+            };
+
+            if changed {
+                self.notified_temperature = Some(temperature);
+                self.notify_subscribers(
+                    Notification::Temperature,
+                    &encode_temperature(temperature),
+                );
+            }
+        }
+
+        self.schedule_temperature_measure();
+    }
+
+    /// Raise the alarm once the temperature leaves the thresholds, subscribed centrals are
+    /// notified about every change of the level
+    fn check_temperature_thresholds(&mut self, temperature: i32) {
+        let level = self
+            .temperature_thresholds
+            .level(self.temperature_level, temperature);
+
+        if level == self.temperature_level {
+            return;
+        }
+
+        rprintln!("App: Temperature {} m°C is {:?}", temperature, level);
+
+        self.temperature_level = level;
+        self.notify_subscribers(Notification::TemperatureAlarm, &[level as u8]);
+
+        if level != TemperatureLevel::Normal {
+            self.on_alarm();
+        }
+    }
+
+    /// Get the level of the last valid sample relative to the thresholds
+    pub fn temperature_level(&self) -> TemperatureLevel {
+        self.temperature_level
+    }
+
+    pub fn temperature_thresholds(&self) -> TemperatureThresholds {
+        self.temperature_thresholds
+    }
+
+    /// Set the alarm thresholds, they are checked with the next sample
+    pub fn on_set_temperature_thresholds(&mut self, thresholds: TemperatureThresholds) {
+        rprintln!("App::on_set_temperature_thresholds({:?})", thresholds);
+        self.temperature_thresholds = thresholds;
+    }
+
+    /// Add the sample to the history, if the history interval passed since the last one
+    fn record_history(&mut self, temperature: i32) {
+        let interval = match self.temperature_config.history_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let now = self.uptime;
+        let last = self.history.last().map(|sample| sample.timestamp);
+
+        let due = match last {
+            Some(last) => now - last >= interval,
+            None => true,
+        };
+
+        if due {
+            self.history.push(HistorySample {
+                timestamp: now,
+                temperature,
+            });
+        }
+    }
+
+    /// Read the next chunk of the history for connection `conidx`, returns the chunk and its
+    /// length
+    ///
+    /// Every read continues after the samples of the previous one.
+    pub fn read_history(&mut self, conidx: u8) -> ([u8; HISTORY_CHUNK_LEN], usize) {
+        let seq = match self.connections.get(conidx) {
+            Some(connection) => connection.history_seq,
+            None => self.history.oldest_seq(),
+        };
+
+        let now = self.advance_uptime();
+        let (chunk, len, next_seq) = self.history.encode_chunk(seq, now);
+
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.history_seq = next_seq;
+        }
+
+        (chunk, len)
+    }
+
+    /// Get the time since boot, which keeps counting when `TimerDriver::now` wraps around
+    fn advance_uptime(&mut self) -> Duration {
+        let now = T::now();
+        if let Some(last) = self.uptime_now {
+            self.uptime += T::elapsed(last, now);
+        }
+        self.uptime_now = Some(now);
+
+        self.uptime
+    }
+
+    /// Temperature history command handler
+    pub fn on_history_command(&mut self, conidx: u8, command: HistoryCommand) {
+        rprintln!("App::on_history_command({}, {:?})", conidx, command);
+
+        match command {
+            HistoryCommand::Clear => self.history.clear(),
+            HistoryCommand::Resume(seq) => {
+                if let Some(connection) = self.connections.get_mut(conidx) {
+                    connection.history_seq = seq;
+                }
+            }
+        }
+    }
+
+    /// Start the timer for the next temperature sample, replaces a running one
+    fn schedule_temperature_measure(&mut self) {
+        if let Some((timer, expired)) = self.temperature_timer.take() {
+            if !expired.get() {
+                timer.cancel();
+            }
+        }
+
+        let sink = match self.event_sink {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        let expired = Rc::new(Cell::new(false));
+        let timer_expired = expired.clone();
+        let timer = T::create(
+            self.temperature_config.interval(),
+            Box::new(move || {
+                timer_expired.set(true);
+                sink(AppEvent::TemperatureMeasure);
+            }),
+        );
+
+        self.temperature_timer = timer.map(|timer| (timer, expired));
+    }
+
+    pub fn temperature_calibration(&self) -> TemperatureCalibration {
+        self.temperature_calibration
+    }
+
+    /// Get the calibration `command` results in, a new one corrects the current one
+    pub fn calibration_after(
+        &self,
+        command: CalibrationCommand,
+    ) -> Result<TemperatureCalibration, ConfigError> {
+        match command {
+            CalibrationCommand::Reset => Ok(TemperatureCalibration::FACTORY),
+            CalibrationCommand::Set(correction) => self.temperature_calibration.then(&correction),
+        }
+    }
+
+    /// Temperature calibration handler, the calibration is stored in flash by the next
+    /// `StorePersistent`
+    pub fn on_calibrate_temperature(&mut self, command: CalibrationCommand) {
+        rprintln!("App::on_calibrate_temperature({:?})", command);
+
+        let calibration = match self.calibration_after(command) {
+            Ok(calibration) => calibration,
+            Err(error) => {
+                rprintln!("App: Calibration rejected {:?}", error);
+                return;
+            }
+        };
+
+        self.temperature_calibration = calibration;
+
+        // The last sample was taken with the old calibration
+        self.temperature = None;
+
+        // Erasing and programming the flash takes tens of ms, too long for a GATT handler
+        match self.event_sink {
+            Some(sink) => sink(AppEvent::StorePersistent),
+            None => self.on_store_persistent(),
+        }
+    }
+
+    /// Write the settings, which survive power loss, to flash
+    pub fn on_store_persistent(&mut self) {
+        let settings = PersistentSettings {
+            temperature_calibration: self.temperature_calibration,
+        };
+        self.peripherals().store_persistent(&settings);
+    }
+
+    pub fn temperature_config(&self) -> TemperatureConfig {
+        self.temperature_config
+    }
+
+    /// Set the temperature sampling, the next sample is taken after the new interval
+    pub fn on_set_temperature_config(&mut self, config: TemperatureConfig) {
+        rprintln!("App::on_set_temperature_config({:?})", config);
+        self.temperature_config = config;
+
+        // Only restart a running sampling, it is started by the first `TemperatureMeasure`
+        if self.temperature_timer.is_some() {
+            self.schedule_temperature_measure();
+        }
+    }
+
+    /// Get the battery level in % of the last measurement
+    pub fn battery_level(&mut self) -> u8 {
+        match self.battery_level {
+            Some(level) => level,
+            None => self.measure_battery_level(),
+        }
+    }
+
+    /// Measure battery and core supply, returns the battery level
+    fn measure_battery_level(&mut self) -> u8 {
+        let voltage = self.peripherals().get_battery_voltage();
+        let vddd = self.peripherals().get_vddd_voltage();
+        let level = self.peripherals().battery_type().level(voltage);
+
+        rprintln!(
+            "App: Battery {} mV -> {}%, VDDD {} mV",
+            voltage,
+            level,
+            vddd
+        );
+
+        let status = self.supply_thresholds().evaluate(voltage, vddd);
+        if status.is_marginal() {
+            rprintln!("App: Supply is marginal {:?}", status);
+        }
+
+        self.battery_level = Some(level);
+        self.supply_status = Some(status);
+        level
+    }
+
+    /// Get the supply status of the last measurement
+    pub fn supply_status(&mut self) -> SupplyStatus {
+        if self.supply_status.is_none() {
+            self.measure_battery_level();
+        }
+
+        self.supply_status.unwrap()
+    }
+
+    /// Check if the last measurement found the supply below a threshold
+    pub fn is_supply_marginal(&self) -> bool {
+        match self.supply_status {
+            Some(status) => status.is_marginal(),
+            None => false,
+        }
+    }
+
+    pub fn supply_thresholds(&mut self) -> SupplyThresholds {
+        match self.supply_thresholds {
+            Some(thresholds) => thresholds,
+            None => SupplyThresholds::for_battery(self.peripherals().battery_type()),
+        }
+    }
+
+    /// Replace the default thresholds of the battery type, the last measurement is re-evaluated
+    pub fn set_supply_thresholds(&mut self, thresholds: SupplyThresholds) {
+        self.supply_thresholds = Some(thresholds);
+
+        if let Some(status) = self.supply_status {
+            self.supply_status = Some(thresholds.evaluate(status.battery, status.vddd));
+        }
+    }
+
+    /// Check if the supply can take `sound`, power hungry sounds need a healthy supply
+    pub fn can_play(&self, sound: Sound) -> bool {
+        !(sound.is_power_hungry() && self.is_supply_marginal())
+    }
+
+    /// Play `sound`, unless the supply can't take it
+    fn play_sound(&mut self, sound: Sound, repeat: bool) {
+        if !self.can_play(sound) {
+            rprintln!("App: Refused to play {:?} on a marginal supply", sound);
+            return;
+        }
+
+        self.peripherals().play_sound(sound, repeat, None);
+    }
+
+    /// Battery measurement handler, notifies subscribed centrals if the level changed
+    pub fn on_battery_measure(&mut self) {
+        // The timer already expired, so it must not be cancelled
+        self.battery_timer = None;
+
+        let previous = self.battery_level;
+        let level = self.measure_battery_level();
+
+        if previous != Some(level) {
+            self.notify_subscribers(Notification::BatteryLevel, &[level]);
+        }
+
+        self.battery_timer =
+            self.start_event_timer(BATTERY_SAMPLE_INTERVAL, AppEvent::BatteryMeasure);
+    }
+
+    /// Get the value of the external sensor `index` (in `SENSORS`) of the last sample
+    pub fn sensor_value(&mut self, index: usize) -> Result<i32, SensorError> {
+        match self.sensor_values[index] {
+            Some(value) => value,
+            None => self.measure_sensor(index),
+        }
+    }
+
+    fn measure_sensor(&mut self, index: usize) -> Result<i32, SensorError> {
+        let sensor = &SENSORS[index];
+        let value = match self.peripherals().read_adc(&sensor.adc) {
+            Some(millivolts) => sensor.curve.convert(millivolts),
+            None => Err(SensorError::Unavailable),
+        };
+        if let Err(error) = value {
+            rprintln!("App: {} rejected {:?}", sensor.name, error);
+        }
+
+        self.sensor_values[index] = Some(value);
+        value
+    }
+
+    /// External sensor sampling handler, notifies subscribed centrals about every sensor, which
+    /// changed by more than its delta since the last notification
+    pub fn on_sensor_measure(&mut self) {
+        // The timer already expired, so it must not be cancelled
+        self.sensor_timer = None;
+
+        for (index, sensor) in SENSORS.iter().enumerate() {
+            // A rejected reading is not notified, subscribers keep the last valid value
+            if let Ok(value) = self.measure_sensor(index) {
+                let changed = match self.notified_sensor_values[index] {
+                    Some(notified) => (value - notified).unsigned_abs() > sensor.delta,
+                    None => true,
+                };
+
+                if changed {
+                    self.notified_sensor_values[index] = Some(value);
+                    self.notify_subscribers(
+                        Notification::Sensor(index as u8),
+                        &encode_sensor_value(value),
+                    );
+                }
+            }
+        }
+
+        self.sensor_timer = self.start_event_timer(SENSOR_SAMPLE_INTERVAL, AppEvent::SensorMeasure);
+    }
+
+    /// Send `value` to every connection, which subscribed to `notification`
+    fn notify_subscribers(&self, notification: Notification, value: &[u8]) {
+        for connection in self.connections.iter() {
+            if connection.subscriptions.contains(notification) {
+                BLE::notify(connection.conidx, notification, value);
+            }
+        }
+    }
+
+    /// Subscription handler (write to a client characteristic configuration descriptor)
+    pub fn on_subscribe(&mut self, conidx: u8, notification: Notification, enabled: bool) {
+        rprintln!(
+            "App::on_subscribe({}, {:?}, {})",
+            conidx,
+            notification,
+            enabled
+        );
+
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.subscriptions.set(notification, enabled);
+        }
+    }
+
+    /// Set the credential, which is checked by `on_unlock_attempt` (replaces the provisioned
+    /// one)
+    pub fn set_unlock_credential(&mut self, credential: UnlockCredential) {
+        self.unlock_credential = Some(credential);
+    }
+
+    /// Get the result of the last unlock attempt of connection `conidx`
+    pub fn unlock_status(&self, conidx: u8) -> UnlockStatus {
+        match self.connections.get(conidx) {
+            Some(connection) => connection.unlock_status,
+            None => UnlockStatus::Locked,
+        }
+    }
+
+    /// Get the failed unlock attempts
+    pub fn failed_unlock_attempts(&self) -> FailedAttempts {
+        self.failed_unlock_attempts
+    }
+
+    /// Check if unlock attempts are rejected, because of an earlier failure
+    pub fn is_unlock_locked_out(&self) -> bool {
+        match &self.unlock_lockout_timer {
+            Some((_, expired)) => !expired.get(),
+            None => false,
+        }
+    }
+
+    /// Start the lockout timer with the current backoff
+    fn start_unlock_lockout(&mut self) {
+        self.cancel_unlock_lockout();
+
+        // Without a timer the lockout would never end, so there is none
+        let expired = Rc::new(Cell::new(false));
+        let timer_expired = expired.clone();
+        self.unlock_lockout_timer = T::create(
+            self.failed_unlock_attempts.lockout(),
+            Box::new(move || timer_expired.set(true)),
+        )
+        .map(|timer| (timer, expired));
+    }
+
+    /// Cancel the lockout timer
+    fn cancel_unlock_lockout(&mut self) {
+        if let Some((timer, expired)) = self.unlock_lockout_timer.take() {
+            // The timer already expired, so it must not be cancelled
+            if !expired.get() {
+                timer.cancel();
+            }
+        }
+    }
+
+    /// Unlock attempt handler, a successful unlock also stops the alarm
+    ///
+    /// Every failure locks out further attempts for an exponentially growing time. Without a
+    /// provisioned credential every attempt fails (it is not counted, nothing can be guessed).
+    pub fn on_unlock_attempt(&mut self, conidx: u8, credential: &[u8]) -> UnlockStatus {
+        if self.connections.get(conidx).is_none() {
+            return UnlockStatus::Locked;
+        }
+
+        let matches = self
+            .unlock_credential
+            .map(|expected| expected.matches(credential));
+
+        let status = if self.is_unlock_locked_out() {
+            rprintln!("App::on_unlock_attempt({}) -> locked out", conidx);
+            UnlockStatus::LockedOut
+        } else if cfg!(feature = "test_open") || matches == Some(true) {
+            rprintln!("App::on_unlock_attempt({}) -> true", conidx);
+            self.on_unlock_success();
+            UnlockStatus::Unlocked
+        } else if matches.is_none() {
+            rprintln!("App::on_unlock_attempt({}) -> no credential", conidx);
+            UnlockStatus::Failed
         } else {
-            self.cancel_hibernation_timer();
+            rprintln!("App::on_unlock_attempt({}) -> false", conidx);
+            self.on_unlock_failure(conidx);
+            UnlockStatus::Failed
+        };
+
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.unlock_status = status;
         }
+
+        status
     }
 
-    /// Disonnect event handler
-    pub fn on_disconnect(&mut self) {
-        self.connection_handle = None;
-        self.peripherals
-            .as_mut()
-            .unwrap()
-            .play_sound(Sound::Disconnected, false, None);
-        self.start_hibernation_timer();
+    fn on_unlock_success(&mut self) {
+        // The alarm ends in `Idle`, the unlocking central is still connected
+        if self.is_alarm_on() && self.transition(AppState::Idle).is_ok() {
+            if !self.connections.is_empty() {
+                let _ = self.transition(AppState::Connected);
+            }
+            self.update_led();
+        }
+
+        self.failed_unlock_attempts = FailedAttempts::new();
+        self.cancel_unlock_lockout();
+        self.play_sound(Sound::UnlockSuccess, false);
+    }
+
+    fn on_unlock_failure(&mut self, conidx: u8) {
+        self.failed_unlock_attempts.record_failure();
+        self.start_unlock_lockout();
+
+        // Failing must not silence the alarm
+        if !self.is_alarm_on() {
+            self.play_sound(Sound::UnlockFail, false);
+        }
+
+        if self.failed_unlock_attempts.alarm() {
+            self.on_alarm();
+        }
+
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.failed_unlock_attempts = connection.failed_unlock_attempts.saturating_add(1);
+            if connection.failed_unlock_attempts >= UNLOCK_MAX_ATTEMPTS_PER_CONNECTION {
+                BLE::disconnect(conidx);
+            }
+        }
+    }
+
+    /// Set the key, which is used to check the authentication response (replaces the
+    /// provisioned one)
+    pub fn set_device_key(&mut self, key: DeviceKey) {
+        self.device_key = Some(key);
+    }
+
+    /// Check if connection `conidx` passed the challenge-response authentication
+    pub fn is_authenticated(&self, conidx: u8) -> bool {
+        if cfg!(feature = "test_open") {
+            return true;
+        }
+
+        match self.connections.get(conidx) {
+            Some(connection) => connection.auth.is_authenticated(),
+            None => false,
+        }
+    }
+
+    /// Nonce request handler, starts a new challenge for connection `conidx`
+    pub fn on_auth_nonce_request(&mut self, conidx: u8) -> AuthBlock {
+        let mut nonce = [0; AUTH_BLOCK_LEN];
+        self.peripherals().random_bytes(&mut nonce);
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.auth.challenge(nonce);
+        }
+
+        nonce
+    }
+
+    /// Authentication response handler of connection `conidx`
+    pub fn on_auth_response(&mut self, conidx: u8, response: &[u8]) -> bool {
+        let nonce = match self
+            .connections
+            .get_mut(conidx)
+            .and_then(|connection| connection.auth.take_nonce())
+        {
+            Some(nonce) => nonce,
+            None => return false,
+        };
+
+        let expected = match self.device_key {
+            Some(key) => self.peripherals().aes128_encrypt(key.as_bytes(), &nonce),
+            None => None,
+        };
+        let authenticated = match (self.connections.get_mut(conidx), expected) {
+            (Some(connection), Some(expected)) => connection.auth.verify(response, &expected),
+            _ => false,
+        };
+
+        rprintln!("App::on_auth_response({}) -> {}", conidx, authenticated);
+
+        authenticated
+    }
+
+    /// Get the table of connected centrals
+    pub fn connections(&self) -> &ConnectionTable<APP_MAX_CONNECTIONS> {
+        &self.connections
+    }
+
+    /// Record a read or write of connection `conidx`, this restarts its idle timer
+    pub fn on_activity(&mut self, conidx: u8) {
+        if let Some(connection) = self.connections.get_mut(conidx) {
+            connection.last_activity = T::now();
+            self.restart_idle_timer(conidx);
+        }
+    }
+
+    /// Get the supervision of inactive connections
+    pub fn idle_config(&self) -> IdleConfig {
+        self.idle_config
+    }
+
+    /// Set the supervision of inactive connections, the idle timers start over
+    pub fn on_set_idle_config(&mut self, config: IdleConfig) {
+        rprintln!("App::on_set_idle_config({:?})", config);
+        self.idle_config = config;
+
+        let conidxs: Vec<u8> = self
+            .connections
+            .iter()
+            .map(|connection| connection.conidx)
+            .collect();
+
+        for conidx in conidxs {
+            self.restart_idle_timer(conidx);
+        }
+    }
+
+    /// Start the idle timer of connection `conidx` with the shorter of the configured and
+    /// the post-write timeout, the timer disconnects the central once it expires
+    fn restart_idle_timer(&mut self, conidx: u8) {
+        let connection_timeout = match self.connections.get(conidx) {
+            Some(connection) => connection.idle_timeout,
+            None => return,
+        };
+
+        let idle_timeout = match (self.idle_config.timeout(), connection_timeout) {
+            (Some(configured), Some(connection)) => Some(configured.min(connection)),
+            (configured, connection) => configured.or(connection),
+        };
+
+        match idle_timeout {
+            Some(idle_timeout) => self.idle_timers.start(
+                conidx,
+                idle_timeout,
+                Box::new(move || {
+                    rprintln!("App: Connection {} idle, disconnecting", conidx);
+                    BLE::disconnect(conidx);
+                }),
+            ),
+            None => self.idle_timers.cancel(conidx),
+        }
+    }
+
+    /// Get the post-write policies
+    pub fn post_write_config(&self) -> PostWriteConfig {
+        self.post_write_config
+    }
+
+    /// Set the post-write policies, which are applied from the next write on
+    pub fn on_set_post_write_config(&mut self, config: PostWriteConfig) {
+        rprintln!("App::on_set_post_write_config({:?})", config);
+        self.post_write_config = config;
+    }
+
+    /// Apply the post-write policy of `target` after connection `conidx` wrote it
+    pub fn on_after_write(&mut self, conidx: u8, target: PostWriteTarget) {
+        let policy = self.post_write_config.policy(target);
+
+        rprintln!(
+            "App::on_after_write({}, {:?}) -> {:?}",
+            conidx,
+            target,
+            policy
+        );
+
+        match policy {
+            PostWritePolicy::StayConnected => {}
+            PostWritePolicy::Disconnect => BLE::disconnect(conidx),
+            PostWritePolicy::DisconnectWhenIdle(idle_secs) => {
+                if let Some(connection) = self.connections.get_mut(conidx) {
+                    connection.idle_timeout = Some(Duration::from_secs(idle_secs as u64));
+                }
+                self.on_activity(conidx);
+            }
+        }
+    }
+
+    /// Get the connection parameters in use by connection `conidx`
+    pub fn connection_params(&self, conidx: u8) -> Option<ConnectionParams> {
+        self.connections.get(conidx)?.conn_params.current()
+    }
+
+    /// Get the connection parameters requested from the centrals
+    pub fn preferred_connection_params(&self) -> PreferredConnectionParams {
+        self.preferred_conn_params
+    }
+
+    /// Set the connection parameters, which are requested from the next connection on
+    pub fn set_preferred_connection_params(&mut self, params: PreferredConnectionParams) {
+        self.preferred_conn_params = params;
+    }
+
+    /// Connection parameters updated handler, requests the preferred ones if they differ
+    pub fn on_conn_params_updated(&mut self, conidx: u8, params: ConnectionParams) {
+        rprintln!("App::on_conn_params_updated({}, {:?})", conidx, params);
+
+        let preferred = self.preferred_conn_params;
+        let delay = match self.connections.get_mut(conidx) {
+            Some(connection) => {
+                connection.conn_params.on_updated(params);
+                if preferred.accepts(&params) {
+                    None
+                } else {
+                    connection.conn_params.next_request()
+                }
+            }
+            None => return,
+        };
+
+        match delay {
+            Some(delay) => self.schedule_conn_param_request(conidx, delay),
+            None => self.conn_param_timers.cancel(conidx),
+        }
+    }
+
+    /// Connection parameter request rejected handler, retries with a growing delay
+    ///
+    /// The stack does not tell which connection rejected, only the connections with a sent and
+    /// unanswered request can be meant. A request is sent once its timer expired, an update
+    /// answers it by replacing or cancelling the timer.
+    pub fn on_conn_params_rejected(&mut self) {
+        let mut retries = Vec::new();
+        for connection in self.connections.iter_mut() {
+            if self.conn_param_timers.has_expired(connection.conidx) {
+                retries.push((connection.conidx, connection.conn_params.next_request()));
+            }
+        }
+
+        for (conidx, delay) in retries {
+            match delay {
+                Some(delay) => {
+                    rprintln!(
+                        "App::on_conn_params_rejected() -> retry {} in {:?}",
+                        conidx,
+                        delay
+                    );
+                    self.schedule_conn_param_request(conidx, delay);
+                }
+                None => self.conn_param_timers.cancel(conidx),
+            }
+        }
+    }
+
+    /// Send a connection parameter request to connection `conidx` after `delay`
+    fn schedule_conn_param_request(&mut self, conidx: u8, delay: Duration) {
+        let preferred = self.preferred_conn_params;
+        self.conn_param_timers.start(
+            conidx,
+            delay,
+            Box::new(move || BLE::request_connection_params(conidx, &preferred)),
+        );
+    }
+
+    /// Connect event handler
+    pub fn on_connect(&mut self, conidx: Option<u8>) {
+        let conidx = match conidx {
+            Some(conidx) => conidx,
+            None => {
+                if self.connections.is_empty() {
+                    self.cancel_hibernation_timer();
+                }
+                return;
+            }
+        };
+
+        // The stack ended advertising without reporting `AdvertisingStopped`
+        self.advertising = false;
+
+        if self
+            .connections
+            .insert(Connection::new(conidx, T::now()))
+            .is_err()
+        {
+            rprintln!("App::on_connect({}) -> no free slot", conidx);
+            BLE::disconnect(conidx);
+            return;
+        }
+
+        self.restart_idle_timer(conidx);
+
+        // Connecting must not end the alarm, only a successful unlock does
+        if self.is_alarm_on() {
+            self.resume_advertising();
+            return;
+        }
+
+        if self.transition(AppState::Connected).is_err() {
+            return;
+        }
+
+        self.cancel_hibernation_timer();
+        self.play_sound(Sound::Connected, false);
+        self.update_led();
+
+        // Further centrals can connect while slots are free
+        self.resume_advertising();
+    }
+
+    /// Disonnect event handler of connection `conidx`
+    pub fn on_disconnect(&mut self, conidx: u8) {
+        if self.connections.remove(conidx).is_none() {
+            return;
+        }
+
+        self.conn_param_timers.cancel(conidx);
+        self.idle_timers.cancel(conidx);
+
+        // The alarm keeps playing after the central is gone, another one can connect to unlock
+        if self.is_alarm_on() {
+            self.resume_advertising();
+            return;
+        }
+
+        self.play_sound(Sound::Disconnected, false);
+
+        // Other centrals are still connected, the SDK asked to advertise while the slot was taken
+        if !self.connections.is_empty() {
+            self.resume_advertising();
+            return;
+        }
+
+        if self.transition(AppState::Idle).is_err() {
+            return;
+        }
+
+        self.update_led();
+
+        // The SDK already asked to advertise, this restarts the hibernation timer
+        self.on_start_advertising();
     }
 
     /// Alarm event handler
     pub fn on_alarm(&mut self) {
-        self.alarm_on = true;
-        self.peripherals().play_sound(Sound::Alarm, true, None);
+        // Without the siren the alarm would only keep the device awake on a marginal supply
+        if !self.can_play(Sound::Alarm) {
+            rprintln!("App: Alarm refused on a marginal supply");
+            return;
+        }
+
+        if self.transition(AppState::Alarm).is_err() {
+            return;
+        }
+
+        // Hibernating would silence the alarm, advertising lets a central connect and unlock
+        self.cancel_hibernation_timer();
+        self.resume_advertising();
+        self.play_sound(Sound::Alarm, true);
+        self.update_led();
+    }
+
+    /// Check if the alarm is playing
+    pub fn is_alarm_on(&self) -> bool {
+        self.state == AppState::Alarm
     }
 
     pub fn feed_watchdog(&mut self) {
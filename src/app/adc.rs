@@ -0,0 +1,257 @@
+/// Full scale of the GPADC input in mV without attenuation, the LDO trim of the OTP keeps it there
+pub const FULL_SCALE_MV: u32 = 900;
+
+/// Nominal sample of the die temperature sensor at 25 °C (16 bit), used if the OTP holds no
+/// calibration
+pub const TEMP_SAMPLE_AT_25C: u16 = 30272;
+
+/// Slope of the die temperature sensor is 1.45 LSB (10 bit) per °C, that is 1.45 * 64 = 92.8 LSB
+/// (16 bit) per °C or 58 / 625 LSB per milli °C
+const TEMP_SLOPE_NUM: i32 = 58;
+const TEMP_SLOPE_DEN: i32 = 625;
+
+/// Scale a 10 bit sample (no oversampling) to the 16 bit range the conversions expect
+pub const fn from_10bit(sample: u16) -> u16 {
+    sample << 6
+}
+
+/// Convert a 16 bit sample into mV, `attenuation` is the input divider (1 - 4)
+pub const fn millivolts(sample: u16, attenuation: u32) -> u16 {
+    ((sample as u32 * FULL_SCALE_MV * attenuation) >> 16) as u16
+}
+
+/// Convert a 16 bit sample of the die temperature sensor into milli °C, `at_25c` is the sample
+/// of the sensor at 25 °C
+///
+/// Truncates towards zero like the float formula it replaces.
+pub const fn millicelsius(sample: u16, at_25c: u16) -> i32 {
+    25_000 + (sample as i32 - at_25c as i32) * TEMP_SLOPE_DEN / TEMP_SLOPE_NUM
+}
+
+/// Positive input of the GPADC, the discriminant is the channel number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AdcChannel {
+    /// External pin P0_1
+    P0_1 = 0,
+    /// External pin P0_2
+    P0_2 = 1,
+    /// External pin P0_6
+    P0_6 = 2,
+    /// External pin P0_7
+    P0_7 = 3,
+    /// Die temperature sensor
+    Temp = 4,
+    /// Battery input of the DCDC in buck mode
+    VbatHigh = 5,
+    /// Battery input of the DCDC in boost mode
+    VbatLow = 6,
+    /// Core supply
+    Vddd = 7,
+}
+
+impl AdcChannel {
+    /// Number of channels
+    pub const COUNT: usize = 8;
+
+    /// Check if the channel is a pin, which has to be switched to its analog function
+    pub const fn is_external(self) -> bool {
+        (self as u8) < 4
+    }
+}
+
+/// Input divider in front of the GPADC, extends the full scale to `factor() * FULL_SCALE_MV`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdcAttenuation {
+    X1,
+    X2,
+    X3,
+    X4,
+}
+
+impl AdcAttenuation {
+    pub const fn factor(self) -> u32 {
+        match self {
+            AdcAttenuation::X1 => 1,
+            AdcAttenuation::X2 => 2,
+            AdcAttenuation::X3 => 3,
+            AdcAttenuation::X4 => 4,
+        }
+    }
+}
+
+/// Number of conversions averaged into one sample
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdcAveraging {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X128,
+}
+
+/// Time the input is sampled before each conversion, in multiples of 8 ADC clock cycles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdcSampleTime {
+    Cycles1X8,
+    Cycles2X8,
+    Cycles3X8,
+    Cycles4X8,
+    Cycles5X8,
+    Cycles6X8,
+    Cycles7X8,
+    Cycles8X8,
+    Cycles9X8,
+    Cycles10X8,
+    Cycles11X8,
+    Cycles12X8,
+    Cycles13X8,
+    Cycles14X8,
+    Cycles15X8,
+}
+
+/// Configuration of a single GPADC sample
+///
+/// The chopper is always on, it cancels the offset of the input stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdcSettings {
+    pub channel: AdcChannel,
+    pub attenuation: AdcAttenuation,
+    pub averaging: AdcAveraging,
+    pub sample_time: AdcSampleTime,
+}
+
+impl AdcSettings {
+    /// Die temperature, the sensor needs a long sample time
+    pub const TEMPERATURE: Self = Self::new(
+        AdcChannel::Temp,
+        AdcAttenuation::X1,
+        AdcAveraging::X32,
+        AdcSampleTime::Cycles15X8,
+    );
+
+    /// Battery voltage of a cell on VBAT_HIGH, the DCDC runs in buck mode (3.6V full scale)
+    pub const BATTERY_HIGH: Self = Self::new(
+        AdcChannel::VbatHigh,
+        AdcAttenuation::X4,
+        AdcAveraging::X8,
+        AdcSampleTime::Cycles2X8,
+    );
+
+    /// Battery voltage of a cell on VBAT_LOW, the DCDC runs in boost mode (3.6V full scale)
+    pub const BATTERY_LOW: Self = Self::new(
+        AdcChannel::VbatLow,
+        AdcAttenuation::X4,
+        AdcAveraging::X8,
+        AdcSampleTime::Cycles2X8,
+    );
+
+    /// Core supply voltage (1.8V full scale)
+    pub const VDDD: Self = Self::new(
+        AdcChannel::Vddd,
+        AdcAttenuation::X2,
+        AdcAveraging::X8,
+        AdcSampleTime::Cycles2X8,
+    );
+
+    pub const fn new(
+        channel: AdcChannel,
+        attenuation: AdcAttenuation,
+        averaging: AdcAveraging,
+        sample_time: AdcSampleTime,
+    ) -> Self {
+        Self {
+            channel,
+            attenuation,
+            averaging,
+            sample_time,
+        }
+    }
+
+    /// Convert a 16 bit sample taken with these settings into mV at the input
+    pub const fn millivolts(&self, sample: u16) -> u16 {
+        millivolts(sample, self.attenuation.factor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Conversion used before, with soft-float on the target
+    fn millicelsius_f32(sample: u16) -> i32 {
+        25000 + ((sample as i32 - 30272i32) as f32 / (1.45 * 64.0) * 1000.0) as i32
+    }
+
+    /// Conversion used before (`full_scale_mv` at the given attenuation)
+    fn millivolts_reference(sample: u16, full_scale_mv: u32) -> u16 {
+        ((sample as u32 * full_scale_mv) >> 16) as u16
+    }
+
+    #[test]
+    fn millicelsius_matches_float_formula_for_16bit_samples() {
+        // f32 rounds 1.45 * 64 and the quotient, so it is off by one in some places
+        for sample in 0..=u16::MAX {
+            let expected = millicelsius_f32(sample);
+            let actual = millicelsius(sample, TEMP_SAMPLE_AT_25C);
+
+            assert!(
+                (expected - actual).abs() <= 1,
+                "sample {}: {} != {}",
+                sample,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn millicelsius_matches_float_formula_for_10bit_samples() {
+        for sample in 0..1024 {
+            let sample = from_10bit(sample);
+            let expected = millicelsius_f32(sample);
+            let actual = millicelsius(sample, TEMP_SAMPLE_AT_25C);
+
+            assert!(
+                (expected - actual).abs() <= 1,
+                "sample {}: {} != {}",
+                sample,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn millicelsius_reference_points() {
+        assert_eq!(millicelsius(30272, TEMP_SAMPLE_AT_25C), 25_000);
+        // One degree is 92.8 LSB
+        assert_eq!(millicelsius(30272 + 928, TEMP_SAMPLE_AT_25C), 35_000);
+        assert_eq!(millicelsius(30272 - 928, TEMP_SAMPLE_AT_25C), 15_000);
+    }
+
+    #[test]
+    fn millicelsius_uses_calibrated_reference() {
+        assert_eq!(millicelsius(30000, 30000), 25_000);
+        assert_eq!(millicelsius(30000 + 928, 30000), 35_000);
+        assert_eq!(
+            millicelsius(30272, 30000),
+            millicelsius(30544, TEMP_SAMPLE_AT_25C)
+        );
+    }
+
+    #[test]
+    fn millivolts_matches_previous_formula() {
+        for sample in 0..=u16::MAX {
+            assert_eq!(millivolts(sample, 4), millivolts_reference(sample, 3600));
+            assert_eq!(millivolts(sample, 2), millivolts_reference(sample, 1800));
+        }
+
+        for sample in 0..1024 {
+            let sample = from_10bit(sample);
+            assert_eq!(millivolts(sample, 4), millivolts_reference(sample, 3600));
+        }
+    }
+}
@@ -0,0 +1,68 @@
+/// Length of the AES-128 key, the nonce and the response
+pub const AUTH_BLOCK_LEN: usize = 16;
+
+/// One AES block
+pub type AuthBlock = [u8; AUTH_BLOCK_LEN];
+
+/// Per-device AES-128 key, which is shared with the centrals allowed to control the device
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DeviceKey([u8; AUTH_BLOCK_LEN]);
+
+impl DeviceKey {
+    pub const fn new(key: [u8; AUTH_BLOCK_LEN]) -> Self {
+        Self(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; AUTH_BLOCK_LEN] {
+        &self.0
+    }
+}
+
+/// Challenge-response state of a connection
+///
+/// The central reads a nonce and has to write back `AES-128(device key, nonce)`.
+/// Every nonce can be answered only once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AuthState {
+    nonce: Option<AuthBlock>,
+    authenticated: bool,
+}
+
+impl AuthState {
+    pub const fn new() -> Self {
+        Self {
+            nonce: None,
+            authenticated: false,
+        }
+    }
+
+    /// Start a new challenge, this also drops an earlier authentication
+    pub fn challenge(&mut self, nonce: AuthBlock) {
+        self.nonce = Some(nonce);
+        self.authenticated = false;
+    }
+
+    /// Take the nonce of the pending challenge
+    pub fn take_nonce(&mut self) -> Option<AuthBlock> {
+        self.nonce.take()
+    }
+
+    /// Compare the response with the expected one and remember the result
+    pub fn verify(&mut self, response: &[u8], expected: &AuthBlock) -> bool {
+        self.authenticated = constant_time_eq(response, expected);
+        self.authenticated
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+}
+
+/// Compare in constant time, so the timing does not leak how many bytes matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
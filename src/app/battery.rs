@@ -0,0 +1,78 @@
+use core::time::Duration;
+
+use super::adc::AdcSettings;
+
+/// Time between two battery measurements
+pub const BATTERY_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Kind of battery powering the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryType {
+    /// 3V lithium coin cell (eg. CR2032) on VBAT_HIGH, DCDC in buck mode
+    CoinCell,
+    /// 1.5V alkaline AAA cell on VBAT_LOW, DCDC in boost mode
+    Aaa,
+}
+
+/// Discharge curve of a CR2032 under light load as `(mV, %)`, descending
+const COIN_CELL_CURVE: &[(u16, u8)] = &[
+    (3000, 100),
+    (2900, 80),
+    (2800, 60),
+    (2700, 40),
+    (2600, 20),
+    (2500, 10),
+    (2200, 0),
+];
+
+/// Discharge curve of an alkaline AAA cell as `(mV, %)`, descending
+const AAA_CURVE: &[(u16, u8)] = &[
+    (1550, 100),
+    (1450, 80),
+    (1350, 60),
+    (1250, 35),
+    (1150, 15),
+    (1100, 5),
+    (1000, 0),
+];
+
+impl BatteryType {
+    fn curve(self) -> &'static [(u16, u8)] {
+        match self {
+            BatteryType::CoinCell => COIN_CELL_CURVE,
+            BatteryType::Aaa => AAA_CURVE,
+        }
+    }
+
+    /// GPADC settings to measure the cell on the battery input it is connected to
+    pub const fn adc_settings(self) -> AdcSettings {
+        match self {
+            BatteryType::CoinCell => AdcSettings::BATTERY_HIGH,
+            BatteryType::Aaa => AdcSettings::BATTERY_LOW,
+        }
+    }
+
+    /// Estimate the remaining capacity in % by linear interpolation of the discharge curve
+    pub fn level(self, millivolts: u16) -> u8 {
+        let curve = self.curve();
+
+        let (max_mv, max_level) = curve[0];
+        if millivolts >= max_mv {
+            return max_level;
+        }
+
+        for points in curve.windows(2) {
+            let (high_mv, high_level) = points[0];
+            let (low_mv, low_level) = points[1];
+
+            if millivolts >= low_mv {
+                let level = low_level as u32
+                    + (millivolts - low_mv) as u32 * (high_level - low_level) as u32
+                        / (high_mv - low_mv) as u32;
+                return level as u8;
+            }
+        }
+
+        0
+    }
+}
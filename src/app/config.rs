@@ -0,0 +1,622 @@
+use core::{ops::RangeInclusive, time::Duration};
+
+use super::temperature::{TemperatureLevel, TEMPERATURE_RANGE};
+
+/// Reasons to reject a configuration written by a central
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The encoded value has the wrong size
+    InvalidLength,
+    /// A field is outside of its allowed range
+    OutOfRange,
+}
+
+/// Advertising timing, which can be changed at runtime
+///
+/// Encoded as `timeout_secs` followed by `period_ms`, both u16 big endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct AdvertisingConfig {
+    /// Stop advertising and go to hibernation after this many seconds
+    timeout_secs: u16,
+    /// Length of a single advertising period in milliseconds
+    period_ms: u16,
+}
+
+impl AdvertisingConfig {
+    /// Values used until a central configures something else
+    pub const DEFAULT: Self = Self {
+        timeout_secs: 60,
+        period_ms: 4000,
+    };
+
+    /// Allowed range of `timeout_secs`
+    pub const TIMEOUT_SECS_RANGE: RangeInclusive<u16> = 10..=3600;
+
+    /// Allowed range of `period_ms`
+    pub const PERIOD_MS_RANGE: RangeInclusive<u16> = 100..=60000;
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 4;
+
+    /// Create a validated config
+    pub fn new(timeout_secs: u16, period_ms: u16) -> Result<Self, ConfigError> {
+        if !Self::TIMEOUT_SECS_RANGE.contains(&timeout_secs)
+            || !Self::PERIOD_MS_RANGE.contains(&period_ms)
+        {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        Ok(Self {
+            timeout_secs,
+            period_ms,
+        })
+    }
+
+    /// Parse and validate the value of the advertising config characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        if value.len() != Self::ENCODED_LEN {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        Self::new(
+            u16::from_be_bytes([value[0], value[1]]),
+            u16::from_be_bytes([value[2], value[3]]),
+        )
+    }
+
+    /// Encode as value of the advertising config characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let timeout = self.timeout_secs.to_be_bytes();
+        let period = self.period_ms.to_be_bytes();
+
+        [timeout[0], timeout[1], period[0], period[1]]
+    }
+
+    /// Check the ranges again (eg. after reading retained memory)
+    pub fn is_valid(&self) -> bool {
+        Self::new(self.timeout_secs, self.period_ms).is_ok()
+    }
+
+    /// Time after which advertising stops and hibernation starts
+    pub const fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs as u64)
+    }
+
+    /// Length of a single advertising period
+    pub const fn period(&self) -> Duration {
+        Duration::from_millis(self.period_ms as u64)
+    }
+}
+
+impl Default for AdvertisingConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Characteristics whose writes are followed by a `PostWritePolicy`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PostWriteTarget {
+    Led = 0,
+    Unlock = 1,
+    AdvertisingConfig = 2,
+}
+
+/// What happens to the connection after a central wrote a characteristic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostWritePolicy {
+    StayConnected,
+    Disconnect,
+    /// Disconnect once the central did not read or write for this many seconds
+    DisconnectWhenIdle(u16),
+}
+
+impl PostWritePolicy {
+    /// Allowed range of the idle time of `DisconnectWhenIdle`
+    pub const IDLE_SECS_RANGE: RangeInclusive<u16> = 1..=3600;
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 3;
+
+    /// Parse a policy encoded as kind (0: stay, 1: disconnect, 2: when idle) followed by
+    /// the idle seconds as u16 big endian (ignored unless the kind is 2)
+    fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        let idle_secs = u16::from_be_bytes([value[1], value[2]]);
+
+        match value[0] {
+            0 => Ok(Self::StayConnected),
+            1 => Ok(Self::Disconnect),
+            2 if Self::IDLE_SECS_RANGE.contains(&idle_secs) => {
+                Ok(Self::DisconnectWhenIdle(idle_secs))
+            }
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+
+    fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        match self {
+            Self::StayConnected => [0, 0, 0],
+            Self::Disconnect => [1, 0, 0],
+            Self::DisconnectWhenIdle(idle_secs) => {
+                let idle_secs = idle_secs.to_be_bytes();
+                [2, idle_secs[0], idle_secs[1]]
+            }
+        }
+    }
+}
+
+/// `PostWritePolicy` of every `PostWriteTarget`
+///
+/// Encoded as the policies in the order of the `PostWriteTarget` discriminants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PostWriteConfig {
+    policies: [PostWritePolicy; Self::TARGETS],
+}
+
+impl PostWriteConfig {
+    /// Number of `PostWriteTarget`s
+    const TARGETS: usize = 3;
+
+    /// Writing the LED ends the connection, everything else keeps it
+    pub const DEFAULT: Self = Self {
+        policies: [
+            PostWritePolicy::Disconnect,
+            PostWritePolicy::StayConnected,
+            PostWritePolicy::StayConnected,
+        ],
+    };
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = Self::TARGETS * PostWritePolicy::ENCODED_LEN;
+
+    pub fn policy(&self, target: PostWriteTarget) -> PostWritePolicy {
+        self.policies[target as usize]
+    }
+
+    pub fn set_policy(&mut self, target: PostWriteTarget, policy: PostWritePolicy) {
+        self.policies[target as usize] = policy;
+    }
+
+    /// Parse and validate the value of the post-write policy characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        if value.len() != Self::ENCODED_LEN {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        let mut config = Self::DEFAULT;
+        for (policy, value) in config
+            .policies
+            .iter_mut()
+            .zip(value.chunks_exact(PostWritePolicy::ENCODED_LEN))
+        {
+            *policy = PostWritePolicy::decode(value)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Encode as value of the post-write policy characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut value = [0; Self::ENCODED_LEN];
+        for (value, policy) in value
+            .chunks_exact_mut(PostWritePolicy::ENCODED_LEN)
+            .zip(&self.policies)
+        {
+            value.copy_from_slice(&policy.encode());
+        }
+
+        value
+    }
+}
+
+impl Default for PostWriteConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Supervision of inactive connections
+///
+/// Encoded as `timeout_secs` u16 big endian, 0 disables the supervision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdleConfig {
+    /// Disconnect a central which did not read or write for this many seconds
+    timeout_secs: u16,
+}
+
+impl IdleConfig {
+    /// Values used until a central configures something else
+    pub const DEFAULT: Self = Self { timeout_secs: 120 };
+
+    /// Allowed range of `timeout_secs`, if the supervision is enabled
+    pub const TIMEOUT_SECS_RANGE: RangeInclusive<u16> = 10..=3600;
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 2;
+
+    /// Create a validated config, `0` disables the supervision
+    pub fn new(timeout_secs: u16) -> Result<Self, ConfigError> {
+        if timeout_secs != 0 && !Self::TIMEOUT_SECS_RANGE.contains(&timeout_secs) {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        Ok(Self { timeout_secs })
+    }
+
+    /// Parse and validate the value of the idle timeout characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        if value.len() != Self::ENCODED_LEN {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        Self::new(u16::from_be_bytes([value[0], value[1]]))
+    }
+
+    /// Encode as value of the idle timeout characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        self.timeout_secs.to_be_bytes()
+    }
+
+    /// Time of inactivity after which a central is disconnected, `None` if disabled
+    pub const fn timeout(&self) -> Option<Duration> {
+        match self.timeout_secs {
+            0 => None,
+            timeout_secs => Some(Duration::from_secs(timeout_secs as u64)),
+        }
+    }
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Sampling of the die temperature, its notifications and history
+///
+/// Encoded as `interval_secs`, `delta` and `history_interval_secs`, all u16 big endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TemperatureConfig {
+    /// Time between two samples in seconds
+    interval_secs: u16,
+    /// Change in milli °C since the last notification, which triggers the next one
+    delta: u16,
+    /// Minimum time between two samples recorded in the history in seconds, `0` disables it
+    ///
+    /// The history is kept in RAM, which hibernation loses, so the device does not hibernate
+    /// while it is recording.
+    history_interval_secs: u16,
+}
+
+impl TemperatureConfig {
+    /// Values used until a central configures something else, the history is off as it keeps
+    /// the device out of hibernation
+    pub const DEFAULT: Self = Self {
+        interval_secs: 10,
+        delta: 500,
+        history_interval_secs: 0,
+    };
+
+    /// Allowed range of `interval_secs`
+    pub const INTERVAL_SECS_RANGE: RangeInclusive<u16> = 1..=3600;
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 6;
+
+    /// Create a validated config
+    pub fn new(
+        interval_secs: u16,
+        delta: u16,
+        history_interval_secs: u16,
+    ) -> Result<Self, ConfigError> {
+        if !Self::INTERVAL_SECS_RANGE.contains(&interval_secs) {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        Ok(Self {
+            interval_secs,
+            delta,
+            history_interval_secs,
+        })
+    }
+
+    /// Parse and validate the value of the temperature config characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        if value.len() != Self::ENCODED_LEN {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        Self::new(
+            u16::from_be_bytes([value[0], value[1]]),
+            u16::from_be_bytes([value[2], value[3]]),
+            u16::from_be_bytes([value[4], value[5]]),
+        )
+    }
+
+    /// Encode as value of the temperature config characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let interval = self.interval_secs.to_be_bytes();
+        let delta = self.delta.to_be_bytes();
+        let history_interval = self.history_interval_secs.to_be_bytes();
+
+        [
+            interval[0],
+            interval[1],
+            delta[0],
+            delta[1],
+            history_interval[0],
+            history_interval[1],
+        ]
+    }
+
+    pub const fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs as u64)
+    }
+
+    /// Change in milli °C, which triggers a notification
+    pub const fn delta(&self) -> u16 {
+        self.delta
+    }
+
+    /// Minimum time between two history samples, `None` if the history is disabled
+    ///
+    /// Samples are only taken every `interval`, so the effective interval is rounded up to it.
+    pub const fn history_interval(&self) -> Option<Duration> {
+        match self.history_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        }
+    }
+}
+
+impl Default for TemperatureConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Linear correction of the die temperature (`gain * reading + offset`)
+///
+/// Encoded as `gain` in 1/65536 followed by `offset` in milli °C, both i32 big endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct TemperatureCalibration {
+    /// Gain in 1/65536 (65536 = 1.0)
+    gain: i32,
+    /// Offset in milli °C
+    offset: i32,
+}
+
+impl TemperatureCalibration {
+    /// Uncorrected readings, as shipped
+    pub const FACTORY: Self = Self {
+        gain: Self::GAIN_ONE,
+        offset: 0,
+    };
+
+    /// Gain of 1.0
+    const GAIN_ONE: i32 = 1 << 16;
+
+    /// Allowed range of `gain` (0.5 - 2.0)
+    pub const GAIN_RANGE: RangeInclusive<i32> = Self::GAIN_ONE / 2..=Self::GAIN_ONE * 2;
+
+    /// Allowed range of `offset` in milli °C
+    pub const OFFSET_RANGE: RangeInclusive<i32> = -20_000..=20_000;
+
+    /// Minimum distance in milli °C between the measured temperatures of the two points
+    pub const MIN_SPAN: i32 = 5_000;
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 8;
+
+    /// Create a validated calibration
+    pub fn new(gain: i32, offset: i32) -> Result<Self, ConfigError> {
+        if !Self::GAIN_RANGE.contains(&gain) || !Self::OFFSET_RANGE.contains(&offset) {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        Ok(Self { gain, offset })
+    }
+
+    /// Derive the calibration from two `(measured, actual)` pairs in milli °C
+    pub fn from_points(a: (i32, i32), b: (i32, i32)) -> Result<Self, ConfigError> {
+        let (measured_a, actual_a) = (a.0 as i64, a.1 as i64);
+        let (measured_b, actual_b) = (b.0 as i64, b.1 as i64);
+
+        let span = measured_b - measured_a;
+        if span.abs() < Self::MIN_SPAN as i64 {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        let gain = ((actual_b - actual_a) << 16) / span;
+        let offset = actual_a - ((gain * measured_a) >> 16);
+
+        match (i32::try_from(gain), i32::try_from(offset)) {
+            (Ok(gain), Ok(offset)) => Self::new(gain, offset),
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+
+    /// Combine with `next`, which corrects the readings already corrected by `self`
+    pub fn then(&self, next: &Self) -> Result<Self, ConfigError> {
+        let gain = (self.gain as i64 * next.gain as i64) >> 16;
+        let offset = ((self.offset as i64 * next.gain as i64) >> 16) + next.offset as i64;
+
+        match (i32::try_from(gain), i32::try_from(offset)) {
+            (Ok(gain), Ok(offset)) => Self::new(gain, offset),
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+
+    /// Correct a reading in milli °C
+    pub fn apply(&self, reading: i32) -> i32 {
+        (((reading as i64 * self.gain as i64) >> 16) + self.offset as i64) as i32
+    }
+
+    /// Encode as value of the temperature calibration characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let gain = self.gain.to_be_bytes();
+        let offset = self.offset.to_be_bytes();
+
+        [
+            gain[0], gain[1], gain[2], gain[3], offset[0], offset[1], offset[2], offset[3],
+        ]
+    }
+
+    /// Check the ranges again (eg. after reading retained memory)
+    pub fn is_valid(&self) -> bool {
+        Self::new(self.gain, self.offset).is_ok()
+    }
+}
+
+impl Default for TemperatureCalibration {
+    fn default() -> Self {
+        Self::FACTORY
+    }
+}
+
+/// Command written to the temperature calibration characteristic
+///
+/// Encoded as opcode followed by its arguments:
+/// - `0x00`: reset to `TemperatureCalibration::FACTORY`
+/// - `0x01`: two-point calibration, `measured_a`, `actual_a`, `measured_b`, `actual_b` in
+///   milli °C, all i32 big endian. The measured temperatures are read from the device, so they
+///   are corrected by the calibration in use already.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationCommand {
+    Reset,
+    /// Correction of the temperatures read with the calibration in use
+    Set(TemperatureCalibration),
+}
+
+impl CalibrationCommand {
+    /// Size of the encoded two-point calibration
+    pub const MAX_ENCODED_LEN: usize = 17;
+
+    /// Parse and validate the value of the temperature calibration characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        match value {
+            [0x00] => Ok(Self::Reset),
+            [0x01, points @ ..] if value.len() == Self::MAX_ENCODED_LEN => {
+                let field = |idx: usize| {
+                    let field = &points[idx * 4..idx * 4 + 4];
+                    i32::from_be_bytes([field[0], field[1], field[2], field[3]])
+                };
+
+                TemperatureCalibration::from_points((field(0), field(1)), (field(2), field(3)))
+                    .map(Self::Set)
+            }
+            [] | [0x00, ..] | [0x01, ..] => Err(ConfigError::InvalidLength),
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+}
+
+/// Temperatures in milli °C, which raise the alarm
+///
+/// Encoded as `low` and `high` (i32 big endian) followed by `hysteresis` (u16 big endian).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct TemperatureThresholds {
+    /// Alarm below this temperature
+    low: i32,
+    /// Alarm above this temperature
+    high: i32,
+    /// Distance from the threshold the temperature has to return by, before it is normal again
+    hysteresis: u16,
+}
+
+impl TemperatureThresholds {
+    /// Whole operating range, so no valid reading raises the alarm
+    pub const DEFAULT: Self = Self {
+        low: *TEMPERATURE_RANGE.start(),
+        high: *TEMPERATURE_RANGE.end(),
+        hysteresis: 1000,
+    };
+
+    /// Size of the encoded value
+    pub const ENCODED_LEN: usize = 10;
+
+    /// Create validated thresholds, the hysteresis has to fit between them
+    pub fn new(low: i32, high: i32, hysteresis: u16) -> Result<Self, ConfigError> {
+        if !TEMPERATURE_RANGE.contains(&low)
+            || !TEMPERATURE_RANGE.contains(&high)
+            || high - low <= hysteresis as i32
+        {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        Ok(Self {
+            low,
+            high,
+            hysteresis,
+        })
+    }
+
+    /// Parse and validate the value of the temperature thresholds characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        if value.len() != Self::ENCODED_LEN {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        Self::new(
+            i32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            i32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+            u16::from_be_bytes([value[8], value[9]]),
+        )
+    }
+
+    /// Encode as value of the temperature thresholds characteristic
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let low = self.low.to_be_bytes();
+        let high = self.high.to_be_bytes();
+        let hysteresis = self.hysteresis.to_be_bytes();
+
+        [
+            low[0],
+            low[1],
+            low[2],
+            low[3],
+            high[0],
+            high[1],
+            high[2],
+            high[3],
+            hysteresis[0],
+            hysteresis[1],
+        ]
+    }
+
+    /// Check the ranges again (eg. after reading retained memory)
+    pub fn is_valid(&self) -> bool {
+        Self::new(self.low, self.high, self.hysteresis).is_ok()
+    }
+
+    /// Classify `temperature`, given the level of the previous sample
+    pub fn level(&self, previous: TemperatureLevel, temperature: i32) -> TemperatureLevel {
+        let hysteresis = self.hysteresis as i32;
+
+        if temperature > self.high {
+            TemperatureLevel::High
+        } else if temperature < self.low {
+            TemperatureLevel::Low
+        } else {
+            match previous {
+                TemperatureLevel::High if temperature > self.high - hysteresis => {
+                    TemperatureLevel::High
+                }
+                TemperatureLevel::Low if temperature < self.low + hysteresis => {
+                    TemperatureLevel::Low
+                }
+                _ => TemperatureLevel::Normal,
+            }
+        }
+    }
+}
+
+impl Default for TemperatureThresholds {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,114 @@
+use core::time::Duration;
+
+/// Wait before the first request, so the central can finish its service discovery
+pub const CONN_PARAM_REQUEST_DELAY: Duration = Duration::from_secs(1);
+
+/// Requests per connection, before the granted parameters are accepted as they are
+pub const CONN_PARAM_MAX_REQUESTS: u8 = 4;
+
+/// Delay of the first retry, doubled by every further retry
+const CONN_PARAM_RETRY_BASE: Duration = Duration::from_secs(2);
+
+/// Connection parameters granted by the central
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionParams {
+    /// Connection interval in units of 1.25ms
+    pub interval: u16,
+    /// Number of connection events the peripheral may skip
+    pub latency: u16,
+    /// Supervision timeout in units of 10ms
+    pub supervision_timeout: u16,
+}
+
+impl ConnectionParams {
+    pub fn interval(&self) -> Duration {
+        Duration::from_micros(self.interval as u64 * 1250)
+    }
+
+    pub fn supervision_timeout(&self) -> Duration {
+        Duration::from_millis(self.supervision_timeout as u64 * 10)
+    }
+}
+
+/// Connection parameters the peripheral asks the central for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreferredConnectionParams {
+    /// Minimum connection interval in units of 1.25ms
+    pub interval_min: u16,
+    /// Maximum connection interval in units of 1.25ms
+    pub interval_max: u16,
+    /// Number of connection events the peripheral may skip
+    pub latency: u16,
+    /// Supervision timeout in units of 10ms
+    pub supervision_timeout: u16,
+}
+
+impl PreferredConnectionParams {
+    /// 50ms - 100ms interval, no latency, 4s supervision timeout
+    pub const DEFAULT: Self = Self {
+        interval_min: 40,
+        interval_max: 80,
+        latency: 0,
+        supervision_timeout: 400,
+    };
+
+    /// Check if the granted parameters match the preferred ones
+    pub fn accepts(&self, params: &ConnectionParams) -> bool {
+        (self.interval_min..=self.interval_max).contains(&params.interval)
+            && params.latency == self.latency
+            && params.supervision_timeout == self.supervision_timeout
+    }
+}
+
+impl Default for PreferredConnectionParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// State of the connection parameter negotiation of one connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnParamNegotiation {
+    current: Option<ConnectionParams>,
+    requests: u8,
+}
+
+impl ConnParamNegotiation {
+    pub const fn new() -> Self {
+        Self {
+            current: None,
+            requests: 0,
+        }
+    }
+
+    /// Parameters currently in use, `None` until the stack reported them
+    pub fn current(&self) -> Option<ConnectionParams> {
+        self.current
+    }
+
+    /// Number of requests scheduled so far, a request is sent once its timer expired
+    pub fn requests(&self) -> u8 {
+        self.requests
+    }
+
+    /// Remember the parameters in use
+    pub fn on_updated(&mut self, params: ConnectionParams) {
+        self.current = Some(params);
+    }
+
+    /// Count the next request, returns its delay or `None` if all requests are used up
+    pub fn next_request(&mut self) -> Option<Duration> {
+        if self.requests >= CONN_PARAM_MAX_REQUESTS {
+            return None;
+        }
+
+        let delay = match self.requests {
+            0 => CONN_PARAM_REQUEST_DELAY,
+            retry => CONN_PARAM_RETRY_BASE * (1 << (retry - 1)),
+        };
+
+        self.requests += 1;
+
+        Some(delay)
+    }
+}
@@ -0,0 +1,232 @@
+use core::{cell::Cell, time::Duration};
+
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+use super::{
+    auth::AuthState, conn_params::ConnParamNegotiation, unlock::UnlockStatus, TimerDriver,
+};
+
+/// Number of centrals which can be connected at the same time
+///
+/// The SDK has to be configured to accept as many (`CFG_MAX_CONNECTIONS`).
+pub const APP_MAX_CONNECTIONS: usize = 3;
+
+/// Characteristics a central can subscribe to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notification {
+    BatteryLevel,
+    Temperature,
+    TemperatureAlarm,
+    /// External sensor with the index in `SENSORS`
+    Sensor(u8),
+}
+
+/// Set of notifications a central subscribed to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Subscriptions(u8);
+
+impl Subscriptions {
+    pub const NONE: Self = Self(0);
+
+    fn mask(notification: Notification) -> u8 {
+        let bit = match notification {
+            Notification::BatteryLevel => 0,
+            Notification::Temperature => 1,
+            Notification::TemperatureAlarm => 2,
+            Notification::Sensor(index) => 3 + index,
+        };
+
+        1 << bit
+    }
+
+    pub fn contains(self, notification: Notification) -> bool {
+        self.0 & Self::mask(notification) != 0
+    }
+
+    /// Subscribe to or unsubscribe from `notification`
+    pub fn set(&mut self, notification: Notification, enabled: bool) {
+        if enabled {
+            self.0 |= Self::mask(notification);
+        } else {
+            self.0 &= !Self::mask(notification);
+        }
+    }
+}
+
+/// State of one connected central
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Connection {
+    /// Connection index assigned by the stack
+    pub conidx: u8,
+    /// Challenge-response state, protected writes of this central are checked against it
+    pub auth: AuthState,
+    /// Result of the last unlock attempt of this central
+    pub unlock_status: UnlockStatus,
+    /// Failed unlock attempts of this central
+    pub failed_unlock_attempts: u16,
+    pub subscriptions: Subscriptions,
+    /// Time of the last read or write of this central
+    pub last_activity: Duration,
+    /// Disconnect once the central was inactive this long, overrides a longer `IdleConfig`
+    pub idle_timeout: Option<Duration>,
+    pub conn_params: ConnParamNegotiation,
+    /// Sequence number of the next history sample this central reads
+    pub history_seq: u32,
+}
+
+impl Connection {
+    pub const fn new(conidx: u8, now: Duration) -> Self {
+        Self {
+            conidx,
+            auth: AuthState::new(),
+            unlock_status: UnlockStatus::Locked,
+            failed_unlock_attempts: 0,
+            subscriptions: Subscriptions::NONE,
+            last_activity: now,
+            idle_timeout: None,
+            conn_params: ConnParamNegotiation::new(),
+            history_seq: 0,
+        }
+    }
+}
+
+/// Fixed-capacity table of the connected centrals, keyed by connection index
+pub struct ConnectionTable<const N: usize> {
+    slots: [Option<Connection>; N],
+}
+
+impl<const N: usize> ConnectionTable<N> {
+    /// Create an empty table
+    pub const fn new() -> Self {
+        Self { slots: [None; N] }
+    }
+
+    /// Add a connection, an existing one with the same index is replaced
+    ///
+    /// Hands the connection back if the table is full.
+    pub fn insert(&mut self, connection: Connection) -> Result<(), Connection> {
+        let slot = match self.position(connection.conidx) {
+            Some(idx) => &mut self.slots[idx],
+            None => match self.slots.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => slot,
+                None => return Err(connection),
+            },
+        };
+
+        *slot = Some(connection);
+
+        Ok(())
+    }
+
+    /// Remove the connection with index `conidx`
+    pub fn remove(&mut self, conidx: u8) -> Option<Connection> {
+        let idx = self.position(conidx)?;
+        self.slots[idx].take()
+    }
+
+    pub fn get(&self, conidx: u8) -> Option<&Connection> {
+        self.iter().find(|connection| connection.conidx == conidx)
+    }
+
+    pub fn get_mut(&mut self, conidx: u8) -> Option<&mut Connection> {
+        self.iter_mut()
+            .find(|connection| connection.conidx == conidx)
+    }
+
+    /// Iterate over all connections
+    pub fn iter(&self) -> impl Iterator<Item = &Connection> {
+        self.slots.iter().flatten()
+    }
+
+    /// Iterate mutably over all connections
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Connection> {
+        self.slots.iter_mut().flatten()
+    }
+
+    /// Number of connections
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Check if no central is connected
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check if no further central can connect
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    fn position(&self, conidx: u8) -> Option<usize> {
+        self.slots.iter().position(|slot| match slot {
+            Some(connection) => connection.conidx == conidx,
+            None => false,
+        })
+    }
+}
+
+impl<const N: usize> Default for ConnectionTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One-shot timers of which at most one per connection is running
+pub struct ConnectionTimers<T> {
+    /// Timer with a flag, which is set once it expired
+    timers: Vec<(u8, T, Rc<Cell<bool>>)>,
+}
+
+impl<T: TimerDriver> ConnectionTimers<T> {
+    pub const fn new() -> Self {
+        Self { timers: Vec::new() }
+    }
+
+    /// Call `callback` after `delay`, replaces the running timer of connection `conidx`
+    pub fn start(&mut self, conidx: u8, delay: Duration, callback: Box<dyn Fn()>) {
+        self.cancel(conidx);
+
+        let expired = Rc::new(Cell::new(false));
+        let timer_expired = expired.clone();
+        let timer = T::create(
+            delay,
+            Box::new(move || {
+                timer_expired.set(true);
+                callback();
+            }),
+        );
+
+        if let Some(timer) = timer {
+            self.timers.push((conidx, timer, expired));
+        }
+    }
+
+    /// Check if the timer of connection `conidx` expired, its callback ran
+    pub fn has_expired(&self, conidx: u8) -> bool {
+        self.timers
+            .iter()
+            .any(|(timer_conidx, _, expired)| *timer_conidx == conidx && expired.get())
+    }
+
+    /// Stop the timer of connection `conidx`
+    pub fn cancel(&mut self, conidx: u8) {
+        if let Some(idx) = self
+            .timers
+            .iter()
+            .position(|(timer_conidx, _, _)| *timer_conidx == conidx)
+        {
+            // An expired timer must not be cancelled, its handle may be in use again
+            let (_, timer, expired) = self.timers.swap_remove(idx);
+            if !expired.get() {
+                timer.cancel();
+            }
+        }
+    }
+}
+
+impl<T: TimerDriver> Default for ConnectionTimers<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,178 @@
+use super::conn_params::ConnectionParams;
+
+/// Events which are passed from SDK callbacks and interrupts to the app
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppEvent {
+    /// The SDK wants to start advertising
+    StartAdvertising,
+    /// The stack stopped advertising
+    AdvertisingStopped,
+    /// Hibernation should be entered
+    StartHibernation,
+    /// A central connected (`None` if the connection index is invalid)
+    Connect(Option<u8>),
+    /// The central with the connection index disconnected
+    Disconnect(u8),
+    /// The connection parameters of the connection index are in use
+    ConnParamsUpdated(u8, ConnectionParams),
+    /// The central rejected a connection parameter request (the stack does not tell which one)
+    ConnParamsRejected,
+    /// Trigger the alarm
+    Alarm,
+    /// The PWM timer interrupt fired
+    PwmInterrupt,
+    /// The LED pattern timer expired
+    LedTimer,
+    /// Time to measure the battery voltage
+    BatteryMeasure,
+    /// Time to sample the die temperature
+    TemperatureMeasure,
+    /// Time to sample the external sensors
+    SensorMeasure,
+    /// Write the persistent settings to flash, outside of the GATT handler which changed them
+    StorePersistent,
+}
+
+/// Function which queues an event for the app, used by timer callbacks
+pub type EventSink = fn(AppEvent);
+
+/// Events which are counted instead of queued, with the limit of pending occurrences
+///
+/// They are raised by interrupts and timers, so they must not take the slots of the lifecycle
+/// events (connections, advertising).
+const COALESCED_EVENTS: [(AppEvent, u32); 6] = [
+    // Every interrupt is a step of the sound, so all of them are handled
+    (AppEvent::PwmInterrupt, u32::MAX),
+    // One pending occurrence of a timer does the same as several
+    (AppEvent::LedTimer, 1),
+    (AppEvent::BatteryMeasure, 1),
+    (AppEvent::TemperatureMeasure, 1),
+    (AppEvent::SensorMeasure, 1),
+    (AppEvent::StorePersistent, 1),
+];
+
+/// Bounded FIFO queue of `AppEvent`s, which does not allocate
+///
+/// Events in `COALESCED_EVENTS` never take a slot, they are handed out after the queued ones.
+/// The queue itself is not synchronized, the owner has to wrap it in a critical section.
+pub struct EventQueue<const N: usize> {
+    events: [Option<AppEvent>; N],
+    head: usize,
+    len: usize,
+    /// Pending occurrences of each of the `COALESCED_EVENTS`
+    coalesced: [u32; COALESCED_EVENTS.len()],
+    dropped: usize,
+}
+
+impl<const N: usize> EventQueue<N> {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self {
+            events: [None; N],
+            head: 0,
+            len: 0,
+            coalesced: [0; COALESCED_EVENTS.len()],
+            dropped: 0,
+        }
+    }
+
+    /// Append an event, hands it back if the queue is full
+    ///
+    /// Coalesced events always succeed.
+    pub fn push(&mut self, event: AppEvent) -> Result<(), AppEvent> {
+        if let Some(index) = COALESCED_EVENTS.iter().position(|(e, _)| *e == event) {
+            let limit = COALESCED_EVENTS[index].1;
+            let pending = &mut self.coalesced[index];
+            *pending = pending.saturating_add(1).min(limit);
+            return Ok(());
+        }
+
+        if self.len == N {
+            self.dropped = self.dropped.wrapping_add(1);
+            return Err(event);
+        }
+
+        self.events[(self.head + self.len) % N] = Some(event);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Take the oldest queued event, then the coalesced ones
+    pub fn pop(&mut self) -> Option<AppEvent> {
+        if self.len == 0 {
+            let index = self.coalesced.iter().position(|pending| *pending > 0)?;
+            self.coalesced[index] -= 1;
+            return Some(COALESCED_EVENTS[index].0);
+        }
+
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        event
+    }
+
+    /// Number of queued events, without the coalesced ones
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if there are no queued or coalesced events
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.coalesced.iter().all(|pending| *pending == 0)
+    }
+
+    /// Number of events dropped because the queue was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl<const N: usize> Default for EventQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts_do_not_take_slots() {
+        let mut queue = EventQueue::<2>::new();
+
+        for _ in 0..10 {
+            assert_eq!(queue.push(AppEvent::PwmInterrupt), Ok(()));
+            assert_eq!(queue.push(AppEvent::LedTimer), Ok(()));
+        }
+        assert_eq!(queue.push(AppEvent::Connect(Some(0))), Ok(()));
+        assert_eq!(queue.push(AppEvent::Disconnect(0)), Ok(()));
+        assert_eq!(queue.push(AppEvent::Alarm), Err(AppEvent::Alarm));
+        assert_eq!(queue.dropped(), 1);
+
+        // Queued events come first, the PWM interrupts are counted and the timer coalesced
+        assert_eq!(queue.pop(), Some(AppEvent::Connect(Some(0))));
+        assert_eq!(queue.pop(), Some(AppEvent::Disconnect(0)));
+        for _ in 0..10 {
+            assert_eq!(queue.pop(), Some(AppEvent::PwmInterrupt));
+        }
+        assert_eq!(queue.pop(), Some(AppEvent::LedTimer));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queued_events_keep_their_order() {
+        let mut queue = EventQueue::<3>::new();
+
+        for round in 0..3 {
+            assert_eq!(queue.push(AppEvent::Connect(Some(round))), Ok(()));
+            assert_eq!(queue.push(AppEvent::Disconnect(round)), Ok(()));
+            assert_eq!(queue.pop(), Some(AppEvent::Connect(Some(round))));
+            assert_eq!(queue.pop(), Some(AppEvent::Disconnect(round)));
+        }
+        assert!(queue.is_empty());
+    }
+}
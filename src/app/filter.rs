@@ -0,0 +1,380 @@
+use alloc::boxed::Box;
+
+use super::adc::AdcChannel;
+
+/// Stage of a filter pipeline, which is fed one sample at a time
+///
+/// Stages are chained as tuples, `(A, B)` feeds the output of `A` into `B`.
+pub trait Filter {
+    /// Feed a sample, returns the filtered value
+    fn update(&mut self, sample: i32) -> i32;
+}
+
+impl<A: Filter, B: Filter> Filter for (A, B) {
+    fn update(&mut self, sample: i32) -> i32 {
+        let sample = self.0.update(sample);
+        self.1.update(sample)
+    }
+}
+
+/// Last `N` samples, the oldest one is overwritten
+struct Window<const N: usize> {
+    samples: [i32; N],
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> Window<N> {
+    const fn new() -> Self {
+        const { assert!(N > 0, "the window needs room for a sample") };
+
+        Self {
+            samples: [0; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Append a sample, returns the one it replaced
+    fn push(&mut self, sample: i32) -> Option<i32> {
+        let replaced = if self.len == N {
+            Some(self.samples[self.next])
+        } else {
+            self.len += 1;
+            None
+        };
+
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+
+        replaced
+    }
+
+    fn as_slice(&self) -> &[i32] {
+        &self.samples[..self.len]
+    }
+}
+
+/// Average of the last `N` samples (of fewer until `N` samples were fed)
+pub struct MovingAverage<const N: usize> {
+    window: Window<N>,
+    sum: i64,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub const fn new() -> Self {
+        Self {
+            window: Window::new(),
+            sum: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for MovingAverage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    fn update(&mut self, sample: i32) -> i32 {
+        if let Some(replaced) = self.window.push(sample) {
+            self.sum -= replaced as i64;
+        }
+        self.sum += sample as i64;
+
+        (self.sum / self.window.len as i64) as i32
+    }
+}
+
+/// Median of the last `N` samples (of fewer until `N` samples were fed), the upper one of an
+/// even number of samples
+///
+/// Drops single spikes without smearing them like an average, `N` should be odd.
+pub struct Median<const N: usize> {
+    window: Window<N>,
+}
+
+impl<const N: usize> Median<N> {
+    pub const fn new() -> Self {
+        Self {
+            window: Window::new(),
+        }
+    }
+}
+
+impl<const N: usize> Default for Median<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Filter for Median<N> {
+    fn update(&mut self, sample: i32) -> i32 {
+        self.window.push(sample);
+
+        let mut sorted = [0; N];
+        let sorted = &mut sorted[..self.window.len];
+        sorted.copy_from_slice(self.window.as_slice());
+        sorted.sort_unstable();
+
+        sorted[sorted.len() / 2]
+    }
+}
+
+/// Exponential moving average, `value += (sample - value) * alpha`
+///
+/// The first sample is taken as it is. The value keeps 16 fractional bits, so it settles on a
+/// constant input instead of getting stuck next to it.
+pub struct Ema {
+    /// Weight of a new sample in 1/65536
+    alpha: u32,
+    /// Filtered value in 1/65536
+    value: Option<i64>,
+}
+
+impl Ema {
+    /// `alpha` is the weight of a new sample in 1/65536 (1 - 65536)
+    pub const fn new(alpha: u32) -> Self {
+        assert!(alpha > 0 && alpha <= 1 << 16, "alpha out of range");
+
+        Self { alpha, value: None }
+    }
+}
+
+impl Filter for Ema {
+    fn update(&mut self, sample: i32) -> i32 {
+        let sample = (sample as i64) << 16;
+        let value = match self.value {
+            Some(value) => value + (((sample - value) * self.alpha as i64) >> 16),
+            None => sample,
+        };
+
+        self.value = Some(value);
+        ((value + (1 << 15)) >> 16) as i32
+    }
+}
+
+/// Replace samples, which differ by more than `max_delta` from the last accepted one, with the
+/// last accepted one
+///
+/// A change, which lasts for more than `max_rejected` samples in a row, is accepted as a real
+/// step of the input.
+pub struct OutlierRejection {
+    max_delta: u32,
+    max_rejected: u8,
+    last: Option<i32>,
+    rejected: u8,
+}
+
+impl OutlierRejection {
+    pub const fn new(max_delta: u32, max_rejected: u8) -> Self {
+        Self {
+            max_delta,
+            max_rejected,
+            last: None,
+            rejected: 0,
+        }
+    }
+}
+
+impl Filter for OutlierRejection {
+    fn update(&mut self, sample: i32) -> i32 {
+        if let Some(last) = self.last {
+            let outlier = (sample - last).unsigned_abs() > self.max_delta;
+            if outlier && self.rejected < self.max_rejected {
+                self.rejected += 1;
+                return last;
+            }
+        }
+
+        self.last = Some(sample);
+        self.rejected = 0;
+        sample
+    }
+}
+
+/// Filter pipeline of each GPADC channel, applied to the 16 bit samples
+#[derive(Default)]
+pub struct AdcFilters {
+    filters: [Option<Box<dyn Filter>>; AdcChannel::COUNT],
+}
+
+impl AdcFilters {
+    /// Set the pipeline of `channel`, `None` passes the samples as they are
+    pub fn set(&mut self, channel: AdcChannel, filter: Option<Box<dyn Filter>>) {
+        self.filters[channel as usize] = filter;
+    }
+
+    /// Feed a sample of `channel` into its pipeline, returns the filtered sample
+    pub fn apply(&mut self, channel: AdcChannel, sample: u16) -> u16 {
+        match &mut self.filters[channel as usize] {
+            Some(filter) => filter.update(sample as i32).clamp(0, u16::MAX as i32) as u16,
+            None => sample,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Deterministic noise in `-amplitude..=amplitude`
+    fn noise(count: usize, amplitude: i32) -> Vec<i32> {
+        let mut state = 0x1234_5678u32;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 16) as i32 % (amplitude + 1) * if state & 0x8000 == 0 { 1 } else { -1 }
+            })
+            .collect()
+    }
+
+    fn run(filter: &mut impl Filter, samples: &[i32]) -> Vec<i32> {
+        samples
+            .iter()
+            .map(|&sample| filter.update(sample))
+            .collect()
+    }
+
+    fn max_error(values: &[i32], expected: i32) -> u32 {
+        values
+            .iter()
+            .map(|&value| (value - expected).unsigned_abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn moving_average_averages_the_available_samples() {
+        let mut filter = MovingAverage::<4>::new();
+        assert_eq!(
+            run(&mut filter, &[10, 20, 30, 40, 50, 60]),
+            [10, 15, 20, 25, 35, 45]
+        );
+    }
+
+    #[test]
+    fn moving_average_reduces_noise() {
+        let samples: Vec<_> = noise(200, 100).iter().map(|n| 30_000 + n).collect();
+        let filtered = run(&mut MovingAverage::<16>::new(), &samples);
+
+        assert!(max_error(&samples, 30_000) > 90);
+        assert!(max_error(&filtered[16..], 30_000) <= 50);
+    }
+
+    #[test]
+    fn median_drops_single_spikes() {
+        let mut samples = [1000; 20];
+        samples[5] = 5000;
+        samples[12] = -3000;
+
+        let filtered = run(&mut Median::<3>::new(), &samples);
+        assert_eq!(filtered, [1000; 20]);
+    }
+
+    #[test]
+    fn median_follows_a_step_after_half_the_window() {
+        let samples = [0, 0, 0, 0, 0, 100, 100, 100, 100];
+        let filtered = run(&mut Median::<5>::new(), &samples);
+
+        assert_eq!(filtered, [0, 0, 0, 0, 0, 0, 0, 100, 100]);
+    }
+
+    #[test]
+    fn ema_starts_with_the_first_sample() {
+        let mut filter = Ema::new(1 << 12);
+        assert_eq!(filter.update(1234), 1234);
+    }
+
+    #[test]
+    fn ema_settles_on_a_step() {
+        // alpha = 1/8
+        let mut samples = [0; 100];
+        samples[1..].iter_mut().for_each(|sample| *sample = 1000);
+
+        let filtered = run(&mut Ema::new(1 << 13), &samples);
+        assert_eq!(filtered[1], 125);
+        assert!(filtered.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(filtered[99], 1000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn ema_rejects_a_zero_alpha() {
+        Ema::new(0);
+    }
+
+    #[test]
+    fn ema_reduces_noise() {
+        let samples: Vec<_> = noise(500, 200).iter().map(|n| -5000 + n).collect();
+        let filtered = run(&mut Ema::new(1 << 12), &samples);
+
+        assert!(max_error(&filtered[100..], -5000) <= 80);
+    }
+
+    #[test]
+    fn outlier_rejection_replaces_outliers() {
+        let samples = [100, 102, 900, 101, -700, 99];
+        let filtered = run(&mut OutlierRejection::new(50, 2), &samples);
+
+        assert_eq!(filtered, [100, 102, 102, 101, 101, 99]);
+    }
+
+    #[test]
+    fn outlier_rejection_accepts_a_lasting_step() {
+        let samples = [100, 100, 500, 500, 500, 500, 100];
+        let filtered = run(&mut OutlierRejection::new(50, 2), &samples);
+
+        assert_eq!(filtered, [100, 100, 100, 100, 500, 500, 500]);
+    }
+
+    #[test]
+    fn pipeline_removes_spikes_and_noise() {
+        let mut samples: Vec<_> = noise(300, 50).iter().map(|n| 20_000 + n).collect();
+        for index in (10..300).step_by(37) {
+            samples[index] = if index % 2 == 0 { 60_000 } else { 0 };
+        }
+
+        let mut pipeline = (
+            OutlierRejection::new(1000, 3),
+            (Median::<5>::new(), Ema::new(1 << 13)),
+        );
+        let filtered = run(&mut pipeline, &samples);
+
+        assert!(max_error(&filtered, 20_000) <= 50);
+    }
+
+    #[test]
+    fn adc_filters_apply_per_channel() {
+        let mut filters = AdcFilters::default();
+        filters.set(AdcChannel::Temp, Some(Box::new(MovingAverage::<2>::new())));
+
+        assert_eq!(filters.apply(AdcChannel::Temp, 100), 100);
+        assert_eq!(filters.apply(AdcChannel::Temp, 200), 150);
+        assert_eq!(filters.apply(AdcChannel::Vddd, 200), 200);
+
+        filters.set(AdcChannel::Temp, None);
+        assert_eq!(filters.apply(AdcChannel::Temp, 300), 300);
+    }
+
+    #[test]
+    fn adc_filters_clamp_to_the_sample_range() {
+        struct Offset(i32);
+
+        impl Filter for Offset {
+            fn update(&mut self, sample: i32) -> i32 {
+                sample + self.0
+            }
+        }
+
+        let mut filters = AdcFilters::default();
+        filters.set(AdcChannel::P0_6, Some(Box::new(Offset(1000))));
+        filters.set(AdcChannel::P0_7, Some(Box::new(Offset(-1000))));
+
+        assert_eq!(filters.apply(AdcChannel::P0_6, u16::MAX - 10), u16::MAX);
+        assert_eq!(filters.apply(AdcChannel::P0_7, 10), 0);
+    }
+}
@@ -0,0 +1,174 @@
+use core::time::Duration;
+
+use super::config::ConfigError;
+
+/// Number of samples kept, older ones are overwritten
+pub const HISTORY_LEN: usize = 64;
+
+/// Samples per chunk, so a chunk fits into a read response with the default ATT MTU
+pub const HISTORY_CHUNK_SAMPLES: usize = 2;
+
+/// Size of an encoded sample (`age_secs`: u32, `temperature`: i32)
+const HISTORY_SAMPLE_LEN: usize = 8;
+
+/// Size of an encoded chunk (sequence number of the first sample followed by the samples)
+pub const HISTORY_CHUNK_LEN: usize = 4 + HISTORY_CHUNK_SAMPLES * HISTORY_SAMPLE_LEN;
+
+/// Temperature recorded at a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistorySample {
+    /// Time of the sample (`App` uptime, which does not wrap like `TimerDriver::now`)
+    pub timestamp: Duration,
+    /// Temperature in milli °C
+    pub temperature: i32,
+}
+
+impl HistorySample {
+    const EMPTY: Self = Self {
+        timestamp: Duration::ZERO,
+        temperature: 0,
+    };
+}
+
+/// Ring buffer of the last `N` samples
+///
+/// Kept in RAM, the samples are lost by hibernation (waking up resets the MCU). Every sample gets
+/// a sequence number, which keeps counting across `clear`, so a reader can resume where it
+/// stopped and notice samples it missed.
+pub struct History<const N: usize> {
+    samples: [HistorySample; N],
+    /// Index of the oldest sample
+    head: usize,
+    len: usize,
+    /// Sequence number of the next sample
+    next_seq: u32,
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [HistorySample::EMPTY; N],
+            head: 0,
+            len: 0,
+            next_seq: 0,
+        }
+    }
+
+    /// Append a sample, overwrites the oldest one if the buffer is full
+    pub fn push(&mut self, sample: HistorySample) {
+        if self.len == N {
+            self.samples[self.head] = sample;
+            self.head = (self.head + 1) % N;
+        } else {
+            self.samples[(self.head + self.len) % N] = sample;
+            self.len += 1;
+        }
+
+        self.next_seq = self.next_seq.wrapping_add(1);
+    }
+
+    /// Drop all samples, sequence numbers are not reused
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sequence number of the next sample
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    /// Sequence number of the oldest sample (equals `next_seq` if empty)
+    pub fn oldest_seq(&self) -> u32 {
+        self.next_seq.wrapping_sub(self.len as u32)
+    }
+
+    /// Get the sample with sequence number `seq`, if it is still kept
+    pub fn get(&self, seq: u32) -> Option<HistorySample> {
+        let offset = seq.wrapping_sub(self.oldest_seq()) as usize;
+        if offset >= self.len {
+            return None;
+        }
+
+        Some(self.samples[(self.head + offset) % N])
+    }
+
+    /// Get the most recent sample
+    pub fn last(&self) -> Option<HistorySample> {
+        self.get(self.next_seq.wrapping_sub(1))
+    }
+
+    /// Encode the samples from `seq` on, which fit into one chunk
+    ///
+    /// Starts at the oldest sample instead, if `seq` was already overwritten or cleared. The
+    /// sample age is relative to `now`. Returns the chunk, its length and the sequence number
+    /// to continue with.
+    pub fn encode_chunk(&self, seq: u32, now: Duration) -> ([u8; HISTORY_CHUNK_LEN], usize, u32) {
+        let behind = self.next_seq.wrapping_sub(seq) as usize;
+        let first = if behind > self.len {
+            self.oldest_seq()
+        } else {
+            seq
+        };
+
+        let mut chunk = [0; HISTORY_CHUNK_LEN];
+        chunk[..4].copy_from_slice(&first.to_be_bytes());
+
+        let mut len = 4;
+        let mut next = first;
+        while let Some(sample) = self.get(next) {
+            if len + HISTORY_SAMPLE_LEN > HISTORY_CHUNK_LEN {
+                break;
+            }
+
+            let age_secs = now.saturating_sub(sample.timestamp).as_secs() as u32;
+            chunk[len..len + 4].copy_from_slice(&age_secs.to_be_bytes());
+            chunk[len + 4..len + 8].copy_from_slice(&sample.temperature.to_be_bytes());
+
+            len += HISTORY_SAMPLE_LEN;
+            next = next.wrapping_add(1);
+        }
+
+        (chunk, len, next)
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Command written to the temperature history characteristic
+///
+/// Encoded as opcode followed by its arguments:
+/// - `0x00`: drop all samples
+/// - `0x01`: continue reading at sequence number `seq` (u32 big endian)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryCommand {
+    Clear,
+    Resume(u32),
+}
+
+impl HistoryCommand {
+    /// Size of the longest command
+    pub const MAX_ENCODED_LEN: usize = 5;
+
+    /// Parse the value of the temperature history characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        match value {
+            [0x00] => Ok(Self::Clear),
+            [0x01, a, b, c, d] => Ok(Self::Resume(u32::from_be_bytes([*a, *b, *c, *d]))),
+            [] | [0x00, ..] | [0x01, ..] => Err(ConfigError::InvalidLength),
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use core::time::Duration;
+
+use super::config::ConfigError;
+
+/// Maximum number of on/off durations of a custom sequence
+pub const LED_SEQUENCE_MAX_STEPS: usize = 8;
+
+/// One flash per second
+const BLINK: [u16; 2] = [100, 900];
+
+/// Two flashes every two seconds
+const DOUBLE_BLINK: [u16; 4] = [150, 150, 150, 1550];
+
+/// Two short flashes close together, like a pulse
+const HEARTBEAT: [u16; 4] = [70, 230, 70, 830];
+
+/// `... --- ...` in morse code, a dot is 150ms
+const SOS: [u16; 18] = [
+    150, 150, 150, 150, 150, 450, // S
+    450, 150, 450, 150, 450, 450, // O
+    150, 150, 150, 150, 150, 1050, // S
+];
+
+/// Custom sequence of on/off durations in ms, starting with on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LedSequence {
+    durations: [u16; LED_SEQUENCE_MAX_STEPS],
+    len: usize,
+    /// Start over after the last step, the LED stays off otherwise
+    repeat: bool,
+}
+
+impl LedSequence {
+    /// Create a sequence of on/off pairs, none of the durations may be 0
+    pub fn new(durations: &[u16], repeat: bool) -> Result<Self, ConfigError> {
+        if durations.is_empty()
+            || durations.len() > LED_SEQUENCE_MAX_STEPS
+            || durations.len() & 1 != 0
+        {
+            return Err(ConfigError::InvalidLength);
+        }
+
+        if durations.contains(&0) {
+            return Err(ConfigError::OutOfRange);
+        }
+
+        let mut sequence = Self {
+            durations: [0; LED_SEQUENCE_MAX_STEPS],
+            len: durations.len(),
+            repeat,
+        };
+        sequence.durations[..durations.len()].copy_from_slice(durations);
+
+        Ok(sequence)
+    }
+
+    pub fn durations(&self) -> &[u16] {
+        &self.durations[..self.len]
+    }
+
+    pub fn repeat(&self) -> bool {
+        self.repeat
+    }
+}
+
+/// What the LED shows
+///
+/// Encoded as pattern id, a custom sequence is followed by its repeat flag and the durations
+/// (u16 big endian):
+/// - `0x00`: off, written it hands the LED back to the event patterns
+/// - `0x01`: on
+/// - `0x02`: blink
+/// - `0x03`: double-blink
+/// - `0x04`: heartbeat
+/// - `0x05`: SOS
+/// - `0x06`: custom
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedPattern {
+    Off,
+    On,
+    Blink,
+    DoubleBlink,
+    Heartbeat,
+    Sos,
+    Custom(LedSequence),
+}
+
+impl LedPattern {
+    /// Size of the longest encoded pattern (custom sequence with all steps)
+    pub const MAX_ENCODED_LEN: usize = 2 + 2 * LED_SEQUENCE_MAX_STEPS;
+
+    /// On/off durations in ms and whether they repeat, empty for a steady LED
+    fn steps(&self) -> (&[u16], bool) {
+        match self {
+            LedPattern::Off | LedPattern::On => (&[], false),
+            LedPattern::Blink => (&BLINK, true),
+            LedPattern::DoubleBlink => (&DOUBLE_BLINK, true),
+            LedPattern::Heartbeat => (&HEARTBEAT, true),
+            LedPattern::Sos => (&SOS, true),
+            LedPattern::Custom(sequence) => (sequence.durations(), sequence.repeat()),
+        }
+    }
+
+    /// Parse the value of the LED pattern characteristic
+    pub fn decode(value: &[u8]) -> Result<Self, ConfigError> {
+        match value {
+            [0x00] => Ok(LedPattern::Off),
+            [0x01] => Ok(LedPattern::On),
+            [0x02] => Ok(LedPattern::Blink),
+            [0x03] => Ok(LedPattern::DoubleBlink),
+            [0x04] => Ok(LedPattern::Heartbeat),
+            [0x05] => Ok(LedPattern::Sos),
+            [0x06, repeat, durations @ ..] => {
+                let repeat = match *repeat {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(ConfigError::OutOfRange),
+                };
+
+                if durations.len() & 1 != 0 || durations.len() > 2 * LED_SEQUENCE_MAX_STEPS {
+                    return Err(ConfigError::InvalidLength);
+                }
+
+                let mut steps = [0; LED_SEQUENCE_MAX_STEPS];
+                for (step, bytes) in steps.iter_mut().zip(durations.chunks(2)) {
+                    *step = u16::from_be_bytes([bytes[0], bytes[1]]);
+                }
+
+                LedSequence::new(&steps[..durations.len() / 2], repeat).map(LedPattern::Custom)
+            }
+            [] | [0x00..=0x05, ..] | [0x06] => Err(ConfigError::InvalidLength),
+            _ => Err(ConfigError::OutOfRange),
+        }
+    }
+
+    /// Encode for the LED pattern characteristic, returns the value and its length
+    pub fn encode(&self) -> ([u8; Self::MAX_ENCODED_LEN], usize) {
+        let mut value = [0; Self::MAX_ENCODED_LEN];
+
+        let id = match self {
+            LedPattern::Off => 0x00,
+            LedPattern::On => 0x01,
+            LedPattern::Blink => 0x02,
+            LedPattern::DoubleBlink => 0x03,
+            LedPattern::Heartbeat => 0x04,
+            LedPattern::Sos => 0x05,
+            LedPattern::Custom(_) => 0x06,
+        };
+        value[0] = id;
+
+        match self {
+            LedPattern::Custom(sequence) => {
+                value[1] = sequence.repeat() as u8;
+                for (bytes, step) in value[2..].chunks_mut(2).zip(sequence.durations()) {
+                    bytes.copy_from_slice(&step.to_be_bytes());
+                }
+
+                (value, 2 + 2 * sequence.durations().len())
+            }
+            _ => (value, 1),
+        }
+    }
+}
+
+/// Patterns shown on app events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LedEventPatterns {
+    /// Shown while a central is connected
+    pub connect: LedPattern,
+    /// Shown once the last central disconnected, until the device hibernates
+    pub disconnect: LedPattern,
+    /// Shown while the alarm is on
+    pub alarm: LedPattern,
+}
+
+impl LedEventPatterns {
+    /// Short flashes keep the LED off most of the time, the disconnect pattern ends with
+    /// hibernation after the advertising timeout
+    pub const DEFAULT: Self = Self {
+        connect: LedPattern::Heartbeat,
+        disconnect: LedPattern::DoubleBlink,
+        alarm: LedPattern::Sos,
+    };
+}
+
+/// Steps through a pattern, the driver calls `advance` once the returned delay passed
+pub struct LedPlayer {
+    pattern: LedPattern,
+    step: usize,
+}
+
+impl LedPlayer {
+    pub const fn new() -> Self {
+        Self {
+            pattern: LedPattern::Off,
+            step: 0,
+        }
+    }
+
+    pub fn pattern(&self) -> LedPattern {
+        self.pattern
+    }
+
+    /// Start `pattern` from its first step, returns the LED state and the delay until the next
+    /// step (`None` if the LED keeps the state)
+    pub fn start(&mut self, pattern: LedPattern) -> (bool, Option<Duration>) {
+        self.pattern = pattern;
+        self.step = 0;
+
+        self.current()
+    }
+
+    /// Go to the next step, returns the LED state and the delay until the next step
+    pub fn advance(&mut self) -> (bool, Option<Duration>) {
+        let (steps, repeat) = self.pattern.steps();
+        if self.step < steps.len() {
+            self.step += 1;
+            if self.step == steps.len() && repeat {
+                self.step = 0;
+            }
+        }
+
+        self.current()
+    }
+
+    fn current(&self) -> (bool, Option<Duration>) {
+        if self.pattern == LedPattern::On {
+            return (true, None);
+        }
+
+        let (steps, _) = self.pattern.steps();
+        match steps.get(self.step) {
+            // Even steps turn the LED on
+            Some(&duration) => (
+                self.step & 1 == 0,
+                Some(Duration::from_millis(duration as u64)),
+            ),
+            // Off or a finished sequence
+            None => (false, None),
+        }
+    }
+}
+
+impl Default for LedPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,18 @@
+use super::config::TemperatureCalibration;
+
+/// Application data which survives power loss (stored in flash)
+///
+/// Only contains plain integers, so any bit pattern read back from flash is a valid value.
+/// Use `is_valid` before applying it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct PersistentSettings {
+    pub temperature_calibration: TemperatureCalibration,
+}
+
+impl PersistentSettings {
+    /// Check every field
+    pub fn is_valid(&self) -> bool {
+        self.temperature_calibration.is_valid()
+    }
+}
@@ -0,0 +1,21 @@
+use super::config::{AdvertisingConfig, TemperatureThresholds};
+
+/// Application data which survives hibernation
+///
+/// Only contains plain integers, so any bit pattern read back from memory is a valid value.
+/// Use `is_valid` before applying it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct RetainedState {
+    pub adv_config: AdvertisingConfig,
+    /// Failed unlock attempts since the last successful unlock
+    pub failed_unlock_attempts: u16,
+    pub temperature_thresholds: TemperatureThresholds,
+}
+
+impl RetainedState {
+    /// Check every field
+    pub fn is_valid(&self) -> bool {
+        self.adv_config.is_valid() && self.temperature_thresholds.is_valid()
+    }
+}
@@ -0,0 +1,210 @@
+use core::time::Duration;
+
+use super::adc::{AdcAttenuation, AdcAveraging, AdcChannel, AdcSampleTime, AdcSettings};
+
+/// Interval of the external sensor sampling
+pub const SENSOR_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Size of a sensor value encoded for GATT (i32 big endian)
+pub const SENSOR_ENCODED_LEN: usize = 4;
+
+/// Number of external sensors, at most 5 (see `Subscriptions`)
+pub const SENSOR_COUNT: usize = 2;
+
+const _: () = assert!(SENSOR_COUNT <= 5);
+
+/// External sensors of the board, each one gets a read/notify characteristic
+pub const SENSORS: [SensorConfig; SENSOR_COUNT] = [
+    // 10k NTC (B 3950) from P0_6 to ground, 10k to the 3V rail
+    SensorConfig {
+        name: "Thermistor",
+        adc: AdcSettings::new(
+            AdcChannel::P0_6,
+            AdcAttenuation::X4,
+            AdcAveraging::X8,
+            AdcSampleTime::Cycles2X8,
+        ),
+        curve: SensorCurve::SteinhartHart {
+            series_ohms: 10_000,
+            supply_mv: 3000,
+            a: 1_125_308,
+            b: 234_711,
+            c: 86,
+        },
+        delta: 500,
+    },
+    // Phototransistor from the 3V rail to P0_7 with a load resistor to ground, lux over mV
+    // from its datasheet
+    SensorConfig {
+        name: "Light",
+        adc: AdcSettings::new(
+            AdcChannel::P0_7,
+            AdcAttenuation::X4,
+            AdcAveraging::X8,
+            AdcSampleTime::Cycles2X8,
+        ),
+        curve: SensorCurve::Table(&[
+            (0, 0),
+            (50, 10),
+            (250, 100),
+            (1000, 500),
+            (2000, 1000),
+            (2800, 2000),
+        ]),
+        delta: 50,
+    },
+];
+
+/// `ln(2)` in 1/65536
+const LN_2: i64 = 45426;
+
+/// 0 °C in mK
+const ZERO_CELSIUS_MK: i64 = 273_150;
+
+/// Reasons a sensor reading is rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorError {
+    /// The voltage in mV is outside of the curve (eg. an open or shorted thermistor)
+    OutOfRange(u16),
+    /// The input of the sensor is not set up as analog input
+    Unavailable,
+}
+
+/// Conversion of the voltage at an external input into the value of the sensor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorCurve {
+    /// `value = mV * gain / 65536 + offset`
+    Linear { gain: i32, offset: i32 },
+    /// NTC thermistor from the input to ground with `series_ohms` to `supply_mv`, the value
+    /// is in milli °C
+    ///
+    /// `1 / T = a + b * ln(R) + c * ln(R)^3` with the coefficients in 1e-9 / K.
+    SteinhartHart {
+        series_ohms: u32,
+        supply_mv: u16,
+        a: i32,
+        b: i32,
+        c: i32,
+    },
+    /// Points of (mV, value) sorted by mV, interpolated linearly and clamped to the first and
+    /// last point
+    Table(&'static [(u16, i32)]),
+}
+
+impl SensorCurve {
+    /// Convert a voltage in mV
+    pub fn convert(&self, millivolts: u16) -> Result<i32, SensorError> {
+        match *self {
+            SensorCurve::Linear { gain, offset } => {
+                Ok(((millivolts as i64 * gain as i64) >> 16) as i32 + offset)
+            }
+            SensorCurve::SteinhartHart {
+                series_ohms,
+                supply_mv,
+                a,
+                b,
+                c,
+            } => steinhart_hart(millivolts, series_ohms, supply_mv, [a, b, c])
+                .ok_or(SensorError::OutOfRange(millivolts)),
+            SensorCurve::Table(points) => {
+                interpolate(points, millivolts).ok_or(SensorError::OutOfRange(millivolts))
+            }
+        }
+    }
+}
+
+/// Check if one of `SENSORS` is connected to `channel`, so its pin has to be set up as analog
+/// input
+pub fn is_sensor_channel(channel: AdcChannel) -> bool {
+    SENSORS.iter().any(|sensor| sensor.adc.channel == channel)
+}
+
+/// Configuration of an external sensor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SensorConfig {
+    /// User description of the characteristic
+    pub name: &'static str,
+    pub adc: AdcSettings,
+    pub curve: SensorCurve,
+    /// Subscribers are notified once the value changed by more than this since the last
+    /// notification
+    pub delta: u32,
+}
+
+/// Encode a sensor value for GATT
+pub fn encode_sensor_value(value: i32) -> [u8; SENSOR_ENCODED_LEN] {
+    value.to_be_bytes()
+}
+
+/// Temperature in milli °C of an NTC thermistor, `None` if the divider is open or shorted
+fn steinhart_hart(
+    millivolts: u16,
+    series_ohms: u32,
+    supply_mv: u16,
+    [a, b, c]: [i32; 3],
+) -> Option<i32> {
+    if millivolts == 0 || millivolts >= supply_mv {
+        return None;
+    }
+
+    let ohms = series_ohms as u64 * millivolts as u64 / (supply_mv - millivolts) as u64;
+    let ohms = u32::try_from(ohms).ok().filter(|&ohms| ohms > 0)?;
+
+    let ln_r = ln(ohms);
+    let ln_r3 = (((ln_r * ln_r) >> 16) * ln_r) >> 16;
+
+    // 1 / T in 1e-9 / K, scaled by 65536
+    let inverse = ((a as i64) << 16) + b as i64 * ln_r + c as i64 * ln_r3;
+    if inverse <= 0 {
+        return None;
+    }
+
+    // 1e12 mK * 65536 / (1e9 * 65536 / K)
+    let kelvin_mk = (1_000_000_000_000i64 << 16) / inverse;
+    i32::try_from(kelvin_mk - ZERO_CELSIUS_MK).ok()
+}
+
+/// Natural logarithm of `x` (> 0) in 1/65536
+fn ln(x: u32) -> i64 {
+    (log2(x) * LN_2) >> 16
+}
+
+/// Binary logarithm of `x` (> 0) in 1/65536
+fn log2(x: u32) -> i64 {
+    let exponent = 31 - x.leading_zeros();
+    let mut result = (exponent as i64) << 16;
+
+    // Mantissa in [1, 2) with 30 fractional bits, every squaring yields one bit of the result
+    let mut mantissa = ((x as u64) << 30) >> exponent;
+    for bit in (0..16).rev() {
+        mantissa = (mantissa * mantissa) >> 30;
+        if mantissa >= 2 << 30 {
+            mantissa >>= 1;
+            result |= 1 << bit;
+        }
+    }
+
+    result
+}
+
+/// Interpolate between the points around `millivolts`, `None` if there are no points
+fn interpolate(points: &[(u16, i32)], millivolts: u16) -> Option<i32> {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return None,
+    };
+
+    if millivolts <= first.0 {
+        return Some(first.1);
+    }
+
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if millivolts <= x1 {
+            let offset = (millivolts - x0) as i64 * (y1 as i64 - y0 as i64);
+            return Some(y0 + (offset / (x1 - x0) as i64) as i32);
+        }
+    }
+
+    Some(last.1)
+}
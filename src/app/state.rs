@@ -0,0 +1,46 @@
+/// State of the application
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppState {
+    /// Neither advertising nor connected (eg. right after boot or a disconnect)
+    Idle,
+    /// Advertising and waiting for a central to connect
+    Advertising,
+    /// At least one central is connected
+    Connected,
+    /// The alarm is playing, no hibernation allowed and only a successful unlock ends it
+    Alarm,
+    /// The MCU is about to enter or is in hibernation
+    Hibernating,
+}
+
+/// Allowed transitions as `(from, to)` pairs, everything else is rejected
+const TRANSITIONS: &[(AppState, AppState)] = &[
+    (AppState::Idle, AppState::Advertising),
+    (AppState::Idle, AppState::Connected),
+    (AppState::Idle, AppState::Alarm),
+    (AppState::Idle, AppState::Hibernating),
+    (AppState::Advertising, AppState::Idle),
+    (AppState::Advertising, AppState::Connected),
+    (AppState::Advertising, AppState::Alarm),
+    (AppState::Advertising, AppState::Hibernating),
+    (AppState::Connected, AppState::Idle),
+    (AppState::Connected, AppState::Alarm),
+    (AppState::Alarm, AppState::Idle),
+];
+
+/// Called on every transition with `(from, to, accepted)`
+pub type TransitionHook = fn(AppState, AppState, bool);
+
+/// A transition which is not part of the transition table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RejectedTransition {
+    pub from: AppState,
+    pub to: AppState,
+}
+
+impl AppState {
+    /// Check the transition table
+    pub fn can_transition_to(self, to: AppState) -> bool {
+        TRANSITIONS.contains(&(self, to))
+    }
+}
@@ -0,0 +1,60 @@
+use super::battery::BatteryType;
+
+/// Minimum voltages below which the supply is considered marginal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupplyThresholds {
+    /// Minimum battery voltage in mV
+    pub battery_min: u16,
+    /// Minimum core supply voltage in mV
+    pub vddd_min: u16,
+}
+
+impl SupplyThresholds {
+    /// Thresholds for a 3V coin cell, below 2.4V its voltage collapses under load
+    pub const COIN_CELL: Self = Self {
+        battery_min: 2400,
+        vddd_min: 850,
+    };
+
+    /// Thresholds for an AAA cell, the boost converter gives up below 1.1V
+    pub const AAA: Self = Self {
+        battery_min: 1100,
+        vddd_min: 850,
+    };
+
+    /// Get the default thresholds of `battery_type`
+    pub const fn for_battery(battery_type: BatteryType) -> Self {
+        match battery_type {
+            BatteryType::CoinCell => Self::COIN_CELL,
+            BatteryType::Aaa => Self::AAA,
+        }
+    }
+
+    /// Compare a measurement against the thresholds
+    pub fn evaluate(&self, battery: u16, vddd: u16) -> SupplyStatus {
+        SupplyStatus {
+            battery,
+            vddd,
+            battery_low: battery < self.battery_min,
+            vddd_low: vddd < self.vddd_min,
+        }
+    }
+}
+
+/// Result of a supply measurement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupplyStatus {
+    /// Battery voltage in mV
+    pub battery: u16,
+    /// Core supply voltage in mV
+    pub vddd: u16,
+    pub battery_low: bool,
+    pub vddd_low: bool,
+}
+
+impl SupplyStatus {
+    /// Check if any voltage is below its threshold
+    pub fn is_marginal(&self) -> bool {
+        self.battery_low || self.vddd_low
+    }
+}
@@ -0,0 +1,41 @@
+use core::ops::RangeInclusive;
+
+/// Temperatures in milli °C the die can be operated at, a reading beyond is implausible
+pub const TEMPERATURE_RANGE: RangeInclusive<i32> = -40_000..=85_000;
+
+/// Size of a temperature encoded for GATT (i32 big endian, milli °C)
+pub const TEMPERATURE_ENCODED_LEN: usize = 4;
+
+/// Reasons a temperature reading is rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemperatureError {
+    /// The converted reading in milli °C is outside of `TEMPERATURE_RANGE`
+    OutOfRange(i32),
+    /// The sensor could not be sampled
+    Unavailable,
+}
+
+/// Check a converted reading in milli °C against `TEMPERATURE_RANGE`
+pub fn check_temperature(temperature: i32) -> Result<i32, TemperatureError> {
+    if TEMPERATURE_RANGE.contains(&temperature) {
+        Ok(temperature)
+    } else {
+        Err(TemperatureError::OutOfRange(temperature))
+    }
+}
+
+/// Encode a temperature in milli °C for GATT
+pub fn encode_temperature(temperature: i32) -> [u8; TEMPERATURE_ENCODED_LEN] {
+    temperature.to_be_bytes()
+}
+
+/// Temperature relative to the alarm thresholds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TemperatureLevel {
+    Normal = 0,
+    /// Below the low threshold
+    Low = 1,
+    /// Above the high threshold
+    High = 2,
+}
@@ -0,0 +1,93 @@
+use core::time::Duration;
+
+use super::auth::constant_time_eq;
+
+/// Length of the credential written to the unlock characteristic
+pub const UNLOCK_CREDENTIAL_LEN: usize = 16;
+
+/// Failed attempts (across connections) from which on every failure triggers the alarm
+pub const UNLOCK_ALARM_THRESHOLD: u16 = 5;
+
+/// Failed attempts within one connection after which the central is disconnected
+pub const UNLOCK_MAX_ATTEMPTS_PER_CONNECTION: u16 = 3;
+
+/// Lockout after the first failed attempt, doubled by every further failure
+const UNLOCK_LOCKOUT_BASE: Duration = Duration::from_secs(1);
+
+/// Upper limit of the lockout
+const UNLOCK_LOCKOUT_MAX: Duration = Duration::from_secs(15 * 60);
+
+/// Secret a central has to submit to unlock the device
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct UnlockCredential([u8; UNLOCK_CREDENTIAL_LEN]);
+
+impl UnlockCredential {
+    pub const fn new(credential: [u8; UNLOCK_CREDENTIAL_LEN]) -> Self {
+        Self(credential)
+    }
+
+    /// Compare in constant time, so the timing does not leak how many bytes matched
+    pub fn matches(&self, submitted: &[u8]) -> bool {
+        constant_time_eq(&self.0, submitted)
+    }
+}
+
+/// Result of the last unlock attempt, readable through the unlock status characteristic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UnlockStatus {
+    /// No attempt in the current connection
+    Locked = 0,
+    /// The last attempt succeeded
+    Unlocked = 1,
+    /// The last attempt failed
+    Failed = 2,
+    /// The last attempt was rejected, because the lockout after a failure was still running
+    LockedOut = 3,
+}
+
+/// Counter of failed unlock attempts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FailedAttempts {
+    /// Since the last successful unlock, kept across connections and hibernation
+    total: u16,
+}
+
+impl FailedAttempts {
+    pub const fn new() -> Self {
+        Self { total: 0 }
+    }
+
+    /// Continue counting from a retained total
+    pub const fn with_total(total: u16) -> Self {
+        Self { total }
+    }
+
+    pub fn record_failure(&mut self) {
+        self.total = self.total.saturating_add(1);
+    }
+
+    pub fn total(&self) -> u16 {
+        self.total
+    }
+
+    /// Lockout after the last failure: `UNLOCK_LOCKOUT_BASE * 2^(total - 1)`, capped
+    pub fn lockout(&self) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+
+        let factor = 1u32.checked_shl(self.total as u32 - 1).unwrap_or(u32::MAX);
+
+        UNLOCK_LOCKOUT_BASE
+            .checked_mul(factor)
+            .map_or(UNLOCK_LOCKOUT_MAX, |lockout| {
+                lockout.min(UNLOCK_LOCKOUT_MAX)
+            })
+    }
+
+    /// Check if the failures should trigger the alarm
+    pub fn alarm(&self) -> bool {
+        self.total >= UNLOCK_ALARM_THRESHOLD
+    }
+}
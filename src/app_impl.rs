@@ -1,9 +1,12 @@
+use core::cell::{Cell, RefCell, UnsafeCell};
+
+use da14531_hal::cm::interrupt::{self, Mutex};
 use da14531_sdk::{
     app_modules::{
         app_common::app::app_prf_enable, app_env_get_conidx, default_app_on_init,
         register_app_callbacks,
     },
-    bindings::default_app_on_disconnect,
+    bindings::{default_app_on_disconnect, gapc_get_conidx},
     ble_stack::{
         host::gap::{
             gapc::task::{GapcConnectionReqInd, GapcDisconnectInd},
@@ -11,22 +14,112 @@ use da14531_sdk::{
         },
         rwble_hl::error::HlErr::GAP_ERR_CANCELED,
     },
-    platform::{arch::register_main_loop_callbacks, core_modules::crypto::aes_init},
+    platform::{
+        arch::{register_main_loop_callbacks, ArchMainLoopCallbackRet},
+        core_modules::crypto::aes_init,
+    },
     register_user_operation_adv,
 };
 use rtt_target::rtt_init_print;
 
-use crate::{app::App, ble::Da14531Ble, peripherals::Da14531Peripherals};
+use crate::{
+    app::{
+        conn_params::ConnectionParams,
+        connection::APP_MAX_CONNECTIONS,
+        event::{AppEvent, EventQueue},
+        App,
+    },
+    ble::Da14531Ble,
+    peripherals::Da14531Peripherals,
+    timer::Da14531Timer,
+};
+
+/// Maximum number of lifecycle events waiting for the main loop
+///
+/// Between two runs of the main loop each connection raises at most `Connect`, two
+/// `ConnParamsUpdated`, `ConnParamsRejected` and `Disconnect`, the advertising and the alarm
+/// at most `StartAdvertising`, `AdvertisingStopped`, `StartHibernation` and `Alarm`. Twice that
+/// leaves room for a slow run. Interrupt and timer events are coalesced and take no slot.
+const APP_EVENT_QUEUE_LEN: usize = 2 * (APP_MAX_CONNECTIONS * 5 + 4);
 
 /// Defines the `Da14531App` for convenience
-type Da14531App = App<Da14531Peripherals, Da14531Ble>;
+type Da14531App = App<Da14531Peripherals, Da14531Ble, Da14531Timer>;
+
+/// Container, which hands out the app to one caller at a time
+struct AppCell {
+    borrowed: Mutex<Cell<bool>>,
+    app: UnsafeCell<Da14531App>,
+}
+
+// Access to `app` is serialized by the `borrowed` flag
+unsafe impl Sync for AppCell {}
 
 /// The actual instance of the app struct
-static mut APP: Da14531App = Da14531App::new();
+static APP: AppCell = AppCell {
+    borrowed: Mutex::new(Cell::new(false)),
+    app: UnsafeCell::new(Da14531App::new()),
+};
+
+/// The app is already borrowed further up the stack (or by the interrupted code)
+#[derive(Debug)]
+pub struct AppBusy;
+
+/// Events pushed by SDK callbacks and interrupts, drained in the main loop
+static EVENTS: Mutex<RefCell<EventQueue<APP_EVENT_QUEUE_LEN>>> =
+    Mutex::new(RefCell::new(EventQueue::new()));
+
+/// Run `f` with exclusive access to the app, fails on re-entrant access
+pub fn try_with_app<R>(f: impl FnOnce(&mut Da14531App) -> R) -> Result<R, AppBusy> {
+    let acquired = interrupt::free(|cs| {
+        let borrowed = APP.borrowed.borrow(cs);
+        if borrowed.get() {
+            false
+        } else {
+            borrowed.set(true);
+            true
+        }
+    });
+
+    if !acquired {
+        return Err(AppBusy);
+    }
+
+    // The flag guarantees that this is the only reference to the app
+    let result = f(unsafe { &mut *APP.app.get() });
+
+    interrupt::free(|cs| APP.borrowed.borrow(cs).set(false));
+
+    Ok(result)
+}
 
-/// Get a mutable reference to the app
-pub fn app() -> &'static mut Da14531App {
-    unsafe { &mut APP }
+/// Run `f` with exclusive access to the app, panics on re-entrant access
+///
+/// Everything but the GATT accesses reaches the app through the event queue. Read requests
+/// have to be answered with the value before the handler returns, and written values are up
+/// to 20 bytes, which would bloat every `AppEvent`. So the handlers in `ble::char_handlers`
+/// (and the activity they record) call the app directly. They run in the main loop like the
+/// event dispatch (BLE kernel messages), never in an interrupt, so they do not interleave
+/// with an event handler.
+pub fn with_app<R>(f: impl FnOnce(&mut Da14531App) -> R) -> R {
+    try_with_app(f).expect("App is already borrowed")
+}
+
+/// Queue an event for the app (safe to call from interrupt context)
+///
+/// Panics if the queue is full: a lost `Connect` or `Disconnect` would leave the connection
+/// table out of sync with the stack, the watchdog reset (in release builds) recovers from that.
+pub fn push_event(event: AppEvent) {
+    interrupt::free(|cs| {
+        let mut events = EVENTS.borrow(cs).borrow_mut();
+        if let Err(event) = events.push(event) {
+            panic!("Event queue full, {:?} would be lost", event);
+        }
+    });
+}
+
+/// Take the oldest queued event
+fn pop_event() -> Option<AppEvent> {
+    interrupt::free(|cs| EVENTS.borrow(cs).borrow_mut().pop())
 }
 
 /// Initialize peripherals
@@ -34,7 +127,10 @@ pub fn app() -> &'static mut Da14531App {
 pub extern "C" fn periph_init() {
     rtt_init_print!(NoBlockSkip, 640);
 
-    app().init_peripherals();
+    with_app(|app| {
+        app.set_event_sink(push_event);
+        app.init_peripherals();
+    });
 }
 
 // Register handler for `default_operation_adv` as default app operation
@@ -43,49 +139,81 @@ register_user_operation_adv!(app_advertising_start_callback);
 /// Trigger advertising in app
 #[inline]
 fn app_advertising_start_callback() {
-    app().on_start_advertising();
+    push_event(AppEvent::StartAdvertising);
 }
-// Register the app_on_init handler
+// Register the main loop handlers
 register_main_loop_callbacks! {
     app_on_init: app_on_init_callback,
+    app_on_system_powered: app_on_system_powered_callback,
 }
 
-/// Initialize AES and run `default_app_on_init` from SDK
+/// Initialize AES, run `default_app_on_init` from SDK and start the measurements
 #[inline]
 pub fn app_on_init_callback() {
     aes_init(false);
 
     default_app_on_init();
+
+    // Timers are available from now on
+    push_event(AppEvent::BatteryMeasure);
+    push_event(AppEvent::TemperatureMeasure);
+    push_event(AppEvent::SensorMeasure);
+}
+
+/// Drain the event queue, this is the only place where events reach the app
+#[inline]
+pub fn app_on_system_powered_callback() -> ArchMainLoopCallbackRet {
+    while let Some(event) = pop_event() {
+        with_app(|app| app.handle_event(event));
+    }
+
+    ArchMainLoopCallbackRet::GOTO_SLEEP
 }
 
 // Register app callback handlers
 register_app_callbacks! {
     app_on_connection: user_app_connection,
     app_on_adv_undirect_complete: user_app_adv_undirect_complete,
-    app_on_disconnect: user_app_disconnect
+    app_on_disconnect: user_app_disconnect,
+    app_on_update_params_rejected: user_app_update_params_rejected
 }
 
 #[inline]
 pub fn user_app_adv_undirect_complete(status: u8) {
     if status == GAP_ERR_CANCELED as u8 {
-        app().on_start_hibernation();
+        push_event(AppEvent::AdvertisingStopped);
     }
 }
 
 #[inline]
-pub fn user_app_connection(conidx: u8, _param: &GapcConnectionReqInd) {
+pub fn user_app_connection(conidx: u8, param: &GapcConnectionReqInd) {
     if app_env_get_conidx(conidx) != GAP_INVALID_CONIDX as u8 {
         app_prf_enable(conidx);
 
-        app().on_connect(Some(conidx as u32));
+        push_event(AppEvent::Connect(Some(conidx)));
+        push_event(AppEvent::ConnParamsUpdated(
+            conidx,
+            ConnectionParams {
+                interval: param.con_interval,
+                latency: param.con_latency,
+                supervision_timeout: param.sup_to,
+            },
+        ));
     } else {
-        app().on_connect(None);
+        push_event(AppEvent::Connect(None));
     }
 }
 
 #[inline]
-pub fn user_app_disconnect(_param: &GapcDisconnectInd) {
+pub fn user_app_disconnect(param: &GapcDisconnectInd) {
+    let conidx = unsafe { gapc_get_conidx(param.conhdl) };
+
     unsafe { default_app_on_disconnect(core::ptr::null()) };
 
-    app().on_disconnect();
+    push_event(AppEvent::Disconnect(conidx));
+}
+
+#[inline]
+pub fn user_app_update_params_rejected() {
+    push_event(AppEvent::ConnParamsRejected);
 }
@@ -1,26 +1,91 @@
-use da14531_sdk::app_modules::{
-    app_common::app::{app_easy_gap_advertise_stop, app_easy_gap_undirected_advertise_start},
-    app_easy_gap_disconnect,
+use core::time::Duration;
+
+use da14531_sdk::{
+    app_modules::{
+        app_common::app::app_easy_gap_advertise_stop, app_easy_gap_disconnect, ms_to_timer_units,
+    },
+    bindings::{
+        app_easy_gap_undirected_advertise_with_timeout_start, custs1_val_ntf_ind_req,
+        gapc_operation_GAPC_UPDATE_PARAMS, CUSTS1_VAL_NTF_REQ, KE_API_ID_TASK_ID_CUSTS1,
+    },
+    ble_stack::{
+        host::gap::gapc::task::KeMsgGapcParamUpdateCmd, profiles::prf::prf_get_task_from_id,
+    },
+    platform::core_modules::{
+        ke::{msg::KernelMessage, task::KeTaskId},
+        rwip::{TASK_APP, TASK_GAPC},
+    },
 };
 
-use crate::app::BleDriver;
+use crate::app::{conn_params::PreferredConnectionParams, connection::Notification, BleDriver};
+
+use self::user_peripheral::notification_att_idx;
 
 pub mod char_handlers;
 pub mod config;
+mod service_db;
 pub mod user_peripheral;
 
+/// Largest notification value, which fits into the default ATT MTU
+const NOTIFICATION_MAX_LEN: u16 = 20;
+
 pub struct Da14531Ble;
 
 impl BleDriver for Da14531Ble {
-    fn start_adverstising() {
-        app_easy_gap_undirected_advertise_start();
+    /// Advertise for `period`, the SDK calls `app_on_adv_undirect_complete` afterwards
+    fn start_adverstising(period: Duration) {
+        unsafe {
+            app_easy_gap_undirected_advertise_with_timeout_start(
+                ms_to_timer_units(period.as_millis() as u32),
+                None,
+            );
+        }
     }
 
     fn stop_adverstising() {
         app_easy_gap_advertise_stop();
     }
 
-    fn disconnect(connection_handle: u32) {
-        app_easy_gap_disconnect(connection_handle as u8);
+    fn disconnect(conidx: u8) {
+        app_easy_gap_disconnect(conidx);
+    }
+
+    /// The central answers with `GAPC_PARAM_UPDATED_IND` or the SDK calls
+    /// `app_on_update_params_rejected`
+    fn request_connection_params(conidx: u8, params: &PreferredConnectionParams) {
+        // Task ID of the connection's GAPC task (`KE_BUILD_ID(TASK_GAPC, conidx)`)
+        let gapc_task_id = ((conidx as u16) << 8) | TASK_GAPC as u16;
+
+        let mut cmd = KeMsgGapcParamUpdateCmd::new(TASK_APP as u16, gapc_task_id);
+
+        cmd.fields().operation = gapc_operation_GAPC_UPDATE_PARAMS as u8;
+        cmd.fields().intv_min = params.interval_min;
+        cmd.fields().intv_max = params.interval_max;
+        cmd.fields().latency = params.latency;
+        cmd.fields().time_out = params.supervision_timeout;
+        cmd.fields().ce_len_min = 0;
+        cmd.fields().ce_len_max = 0;
+
+        cmd.send();
+    }
+
+    /// The value is cut to `NOTIFICATION_MAX_LEN`, the CUSTS1 task sends it to the central
+    fn notify(conidx: u8, notification: Notification, value: &[u8]) {
+        let length = value.len().min(NOTIFICATION_MAX_LEN as usize);
+
+        let mut req =
+            KernelMessage::<CUSTS1_VAL_NTF_REQ, NOTIFICATION_MAX_LEN, custs1_val_ntf_ind_req>::new(
+                TASK_APP as u16,
+                prf_get_task_from_id(KE_API_ID_TASK_ID_CUSTS1 as KeTaskId),
+            );
+
+        req.fields().conidx = conidx;
+        req.fields().notification = true;
+        req.fields().handle = notification_att_idx(notification);
+        req.fields().length = length as u16;
+
+        unsafe { req.fields().value.as_mut_slice(length) }.copy_from_slice(&value[..length]);
+
+        req.send();
     }
 }
@@ -1,31 +1,42 @@
 use da14531_sdk::{
-    app_modules::{app_easy_gap_disconnect, app_env_get_conidx},
+    app_modules::app_env_get_conidx,
     bindings::KE_API_ID_TASK_ID_CUSTS1,
     ble_stack::{
         profiles::{
             custom::custs::custs1::task::{
-                Custs1ValWriteInd, Custs1ValueReqInd, KeMsgDynCusts1ValueReqRsp,
+                Custs1ValWriteInd, Custs1ValueReqInd, KeMsgCusts1ValueReqRsp,
+                KeMsgDynCusts1ValueReqRsp,
             },
             prf::prf_get_task_from_id,
         },
-        rwble_hl::error::HlErr::GAP_ERR_NO_ERROR as ATT_ERR_NO_ERROR,
+        rwble_hl::error::HlErr::{
+            ATT_ERR_APP_ERROR, ATT_ERR_INVALID_ATTRIBUTE_VAL_LEN,
+            GAP_ERR_NO_ERROR as ATT_ERR_NO_ERROR,
+        },
     },
     platform::core_modules::{ke::task::KeTaskId, rwip::TASK_APP},
 };
 
-use crate::app_impl::app;
-
-pub fn led_write_char_write_handler(param: &Custs1ValWriteInd) {
-    let token = unsafe { param.value.as_slice(1) };
-
-    app().on_set_led(token[0] != 0);
-
-    let conidx = app_env_get_conidx(param.conidx);
-    app_easy_gap_disconnect(conidx);
-}
+use crate::{
+    app::{
+        auth::AUTH_BLOCK_LEN,
+        config::{
+            AdvertisingConfig, CalibrationCommand, ConfigError, IdleConfig, PostWriteConfig,
+            PostWriteTarget, TemperatureCalibration, TemperatureConfig, TemperatureThresholds,
+        },
+        connection::Notification,
+        history::{HistoryCommand, HISTORY_CHUNK_LEN},
+        led::LedPattern,
+        sensor::{encode_sensor_value, SENSOR_ENCODED_LEN},
+        temperature::{encode_temperature, TEMPERATURE_ENCODED_LEN},
+        unlock::UNLOCK_CREDENTIAL_LEN,
+    },
+    app_impl::with_app,
+};
 
-pub fn led_read_char_read_handler(param: &Custs1ValueReqInd) {
-    let mut response = KeMsgDynCusts1ValueReqRsp::<1>::new(
+/// Respond to a read request with `value` (`SIZE` is the maximum length of `value`)
+fn send_read_response<const SIZE: u16>(param: &Custs1ValueReqInd, value: &[u8]) {
+    let mut response = KeMsgDynCusts1ValueReqRsp::<SIZE>::new(
         TASK_APP as u16,
         prf_get_task_from_id(KE_API_ID_TASK_ID_CUSTS1 as KeTaskId),
     );
@@ -38,47 +49,359 @@ pub fn led_read_char_read_handler(param: &Custs1ValueReqInd) {
     // Provide the attribute index.
     response.fields().att_idx = param.att_idx;
 
-    // Provide length of the payload (bool = 1)
-    response.fields().length = 1;
+    // Provide length of the payload
+    response.fields().length = value.len() as u16;
 
     // Provide the ATT error code.
     response.fields().status = ATT_ERR_NO_ERROR as u8;
 
     // Copy value
-    let value = unsafe { response.fields().value.as_mut_slice(1) };
-
-    value[0] = if app().get_led_state() { 1 } else { 0 };
+    unsafe { response.fields().value.as_mut_slice(value.len()) }.copy_from_slice(value);
 
     response.send();
 }
 
-pub fn temp_read_char_read_handler(param: &Custs1ValueReqInd) {
-    let mut response = KeMsgDynCusts1ValueReqRsp::<2>::new(
+/// Respond to a read request with the ATT error code `status`
+fn send_error_response(param: &Custs1ValueReqInd, status: u8) {
+    let mut response = KeMsgCusts1ValueReqRsp::new(
         TASK_APP as u16,
         prf_get_task_from_id(KE_API_ID_TASK_ID_CUSTS1 as KeTaskId),
     );
 
-    let conidx = app_env_get_conidx(param.conidx);
-
     // Provide the connection index.
-    response.fields().conidx = conidx;
+    response.fields().conidx = app_env_get_conidx(param.conidx);
 
     // Provide the attribute index.
     response.fields().att_idx = param.att_idx;
 
-    // Provide length of the payload (u16 = 2)
-    response.fields().length = 2;
+    // Force current length to zero.
+    response.fields().length = 0;
 
     // Provide the ATT error code.
-    response.fields().status = ATT_ERR_NO_ERROR as u8;
+    response.fields().status = status;
 
-    // Copy value
-    let value = unsafe { response.fields().value.as_mut_slice(2) };
+    response.send();
+}
+
+/// ATT error code of a rejected write
+fn config_error_to_att(err: ConfigError) -> u8 {
+    match err {
+        ConfigError::InvalidLength => ATT_ERR_INVALID_ATTRIBUTE_VAL_LEN as u8,
+        ConfigError::OutOfRange => ATT_ERR_APP_ERROR as u8,
+    }
+}
+
+pub fn led_write_char_write_handler(param: &Custs1ValWriteInd) {
+    let token = unsafe { param.value.as_slice(1) };
+
+    with_app(|app| {
+        app.on_set_led(token[0] != 0);
+        app.on_after_write(param.conidx, PostWriteTarget::Led);
+    });
+}
 
-    let temp = app().get_temperature();
+pub fn led_read_char_read_handler(param: &Custs1ValueReqInd) {
+    let value = if with_app(|app| app.get_led_state()) {
+        1
+    } else {
+        0
+    };
 
-    value[0] = ((temp >> 8) & 0xff) as u8;
-    value[1] = (temp & 0xff) as u8;
+    // bool = 1
+    send_read_response::<1>(param, &[value]);
+}
 
-    response.send();
+/// Check the written value before it is accepted, returns the ATT error code
+pub fn led_pattern_char_validate(value: &[u8]) -> u8 {
+    match LedPattern::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn led_pattern_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(pattern) = LedPattern::decode(value) {
+        with_app(|app| {
+            app.on_set_led_pattern(pattern);
+            app.on_after_write(param.conidx, PostWriteTarget::Led);
+        });
+    }
+}
+
+pub fn led_pattern_char_read_handler(param: &Custs1ValueReqInd) {
+    let (value, len) = with_app(|app| app.led_pattern()).encode();
+
+    // Only a custom sequence is longer than the pattern id
+    send_read_response::<{ LedPattern::MAX_ENCODED_LEN as u16 }>(param, &value[..len]);
+}
+
+pub fn temp_read_char_read_handler(param: &Custs1ValueReqInd) {
+    match with_app(|app| app.get_temperature()) {
+        // i32 = 4
+        Ok(temp) => send_read_response::<{ TEMPERATURE_ENCODED_LEN as u16 }>(
+            param,
+            &encode_temperature(temp),
+        ),
+        Err(_) => send_error_response(param, ATT_ERR_APP_ERROR as u8),
+    }
+}
+
+/// Check the written value before it is accepted, returns the ATT error code
+pub fn adv_config_char_validate(value: &[u8]) -> u8 {
+    match AdvertisingConfig::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn adv_config_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(config) = AdvertisingConfig::decode(value) {
+        with_app(|app| {
+            app.on_set_advertising_config(config);
+            app.on_after_write(param.conidx, PostWriteTarget::AdvertisingConfig);
+        });
+    }
+}
+
+pub fn adv_config_char_read_handler(param: &Custs1ValueReqInd) {
+    let config = with_app(|app| app.advertising_config());
+
+    // timeout_secs: u16 + period_ms: u16 = 4
+    send_read_response::<{ AdvertisingConfig::ENCODED_LEN as u16 }>(param, &config.encode());
+}
+
+/// Check the length of the submitted credential, returns the ATT error code
+pub fn unlock_char_validate(value: &[u8]) -> u8 {
+    if value.len() == UNLOCK_CREDENTIAL_LEN {
+        ATT_ERR_NO_ERROR as u8
+    } else {
+        ATT_ERR_INVALID_ATTRIBUTE_VAL_LEN as u8
+    }
+}
+
+pub fn unlock_char_write_handler(param: &Custs1ValWriteInd) {
+    let credential = unsafe { param.value.as_slice(param.length as usize) };
+
+    with_app(|app| {
+        app.on_unlock_attempt(param.conidx, credential);
+        app.on_after_write(param.conidx, PostWriteTarget::Unlock);
+    });
+}
+
+pub fn unlock_status_char_read_handler(param: &Custs1ValueReqInd) {
+    let status = with_app(|app| app.unlock_status(param.conidx));
+
+    // UnlockStatus = 1
+    send_read_response::<1>(param, &[status as u8]);
+}
+
+pub fn auth_nonce_char_read_handler(param: &Custs1ValueReqInd) {
+    let nonce = with_app(|app| app.on_auth_nonce_request(param.conidx));
+
+    // AuthBlock = 16
+    send_read_response::<{ AUTH_BLOCK_LEN as u16 }>(param, &nonce);
+}
+
+/// Check the length of the response, returns the ATT error code
+pub fn auth_response_char_validate(value: &[u8]) -> u8 {
+    if value.len() == AUTH_BLOCK_LEN {
+        ATT_ERR_NO_ERROR as u8
+    } else {
+        ATT_ERR_INVALID_ATTRIBUTE_VAL_LEN as u8
+    }
+}
+
+pub fn auth_response_char_write_handler(param: &Custs1ValWriteInd) {
+    let response = unsafe { param.value.as_slice(param.length as usize) };
+
+    with_app(|app| app.on_auth_response(param.conidx, response));
+}
+
+/// Check the written policies before they are accepted, returns the ATT error code
+pub fn post_write_config_char_validate(value: &[u8]) -> u8 {
+    match PostWriteConfig::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn post_write_config_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(config) = PostWriteConfig::decode(value) {
+        with_app(|app| app.on_set_post_write_config(config));
+    }
+}
+
+pub fn post_write_config_char_read_handler(param: &Custs1ValueReqInd) {
+    let config = with_app(|app| app.post_write_config());
+
+    // 3 policies of 3 bytes = 9
+    send_read_response::<{ PostWriteConfig::ENCODED_LEN as u16 }>(param, &config.encode());
+}
+
+/// Check the written idle timeout before it is accepted, returns the ATT error code
+pub fn idle_config_char_validate(value: &[u8]) -> u8 {
+    match IdleConfig::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn idle_config_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(config) = IdleConfig::decode(value) {
+        with_app(|app| app.on_set_idle_config(config));
+    }
+}
+
+pub fn idle_config_char_read_handler(param: &Custs1ValueReqInd) {
+    let config = with_app(|app| app.idle_config());
+
+    // timeout_secs: u16 = 2
+    send_read_response::<{ IdleConfig::ENCODED_LEN as u16 }>(param, &config.encode());
+}
+
+/// Check the written value before it is accepted, returns the ATT error code
+pub fn temp_config_char_validate(value: &[u8]) -> u8 {
+    match TemperatureConfig::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn temp_config_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(config) = TemperatureConfig::decode(value) {
+        with_app(|app| app.on_set_temperature_config(config));
+    }
+}
+
+pub fn temp_config_char_read_handler(param: &Custs1ValueReqInd) {
+    let config = with_app(|app| app.temperature_config());
+
+    // interval_secs: u16 + delta: u16 + history_interval_secs: u16 = 6
+    send_read_response::<{ TemperatureConfig::ENCODED_LEN as u16 }>(param, &config.encode());
+}
+
+/// Check the written value before it is accepted, returns the ATT error code
+///
+/// The correction is combined with the calibration in use, the result has to be in range too.
+pub fn temp_calibration_char_validate(value: &[u8]) -> u8 {
+    match CalibrationCommand::decode(value)
+        .and_then(|command| with_app(|app| app.calibration_after(command)))
+    {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn temp_calibration_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(command) = CalibrationCommand::decode(value) {
+        with_app(|app| app.on_calibrate_temperature(command));
+    }
+}
+
+pub fn temp_calibration_char_read_handler(param: &Custs1ValueReqInd) {
+    let calibration = with_app(|app| app.temperature_calibration());
+
+    // gain: i32 + offset: i32 = 8
+    send_read_response::<{ TemperatureCalibration::ENCODED_LEN as u16 }>(
+        param,
+        &calibration.encode(),
+    );
+}
+
+/// Check the written value before it is accepted, returns the ATT error code
+pub fn temp_history_char_validate(value: &[u8]) -> u8 {
+    match HistoryCommand::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn temp_history_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(command) = HistoryCommand::decode(value) {
+        with_app(|app| app.on_history_command(param.conidx, command));
+    }
+}
+
+pub fn temp_history_char_read_handler(param: &Custs1ValueReqInd) {
+    let (chunk, len) = with_app(|app| app.read_history(param.conidx));
+
+    // The chunk is shorter, once the reader caught up
+    send_read_response::<{ HISTORY_CHUNK_LEN as u16 }>(param, &chunk[..len]);
+}
+
+/// Check the written value before it is accepted, returns the ATT error code
+pub fn temp_thresholds_char_validate(value: &[u8]) -> u8 {
+    match TemperatureThresholds::decode(value) {
+        Ok(_) => ATT_ERR_NO_ERROR as u8,
+        Err(err) => config_error_to_att(err),
+    }
+}
+
+pub fn temp_thresholds_char_write_handler(param: &Custs1ValWriteInd) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    if let Ok(thresholds) = TemperatureThresholds::decode(value) {
+        with_app(|app| app.on_set_temperature_thresholds(thresholds));
+    }
+}
+
+pub fn temp_thresholds_char_read_handler(param: &Custs1ValueReqInd) {
+    let thresholds = with_app(|app| app.temperature_thresholds());
+
+    // low: i32 + high: i32 + hysteresis: u16 = 10
+    send_read_response::<{ TemperatureThresholds::ENCODED_LEN as u16 }>(
+        param,
+        &thresholds.encode(),
+    );
+}
+
+pub fn temp_alarm_char_read_handler(param: &Custs1ValueReqInd) {
+    let level = with_app(|app| app.temperature_level());
+
+    // TemperatureLevel = 1
+    send_read_response::<1>(param, &[level as u8]);
+}
+
+/// Read handler of the external sensor `index` (in `SENSORS`)
+pub fn sensor_char_read_handler(param: &Custs1ValueReqInd, index: u8) {
+    match with_app(|app| app.sensor_value(index as usize)) {
+        // i32 = 4
+        Ok(value) => {
+            send_read_response::<{ SENSOR_ENCODED_LEN as u16 }>(param, &encode_sensor_value(value))
+        }
+        Err(_) => send_error_response(param, ATT_ERR_APP_ERROR as u8),
+    }
+}
+
+pub fn battery_level_char_read_handler(param: &Custs1ValueReqInd) {
+    let level = with_app(|app| app.battery_level());
+
+    // u8 = 1
+    send_read_response::<1>(param, &[level]);
+}
+
+/// Enable or disable `notification` for the writing central (bit 0 of the descriptor)
+pub fn client_config_write_handler(param: &Custs1ValWriteInd, notification: Notification) {
+    let value = unsafe { param.value.as_slice(param.length as usize) };
+
+    let enabled = match value {
+        [low, _] => low & 0x01 != 0,
+        _ => return,
+    };
+
+    with_app(|app| app.on_subscribe(param.conidx, notification, enabled));
 }
@@ -1,42 +1,311 @@
 use da14531_sdk::{
     app_modules::app_custs::{custs1::app_custs1_create_db, CustPrfFuncCallbacks},
     app_modules::{
-        default_handlers_configuration, ms_to_timer_units, DEF_ADV_WITH_TIMEOUT, DEF_SEC_REQ_NEVER,
+        app_common::app::custs_get_func_callbacks, default_handlers_configuration,
+        ms_to_timer_units, DEF_ADV_WITH_TIMEOUT, DEF_SEC_REQ_NEVER,
     },
-    ble_stack::profiles::custom::custs::service_database,
+    ble_stack::{host::att::attm::AttmDesc128, profiles::custom::custs::RomCustPrfCfg},
     perm,
     platform::core_modules::rwip::TASK_ID_CUSTS1,
 };
 
-// Setup service database
-service_database![
-    {
-        etype: service,
-        uuid16: 0xFD6B // Rapitag 16bit UUID
-    },
-    {
-        etype: characteristic,
-        perm: perm!(WR, ENABLE) | perm!(WRITE_COMMAND, ENABLE) | perm!(WRITE_REQ, ENABLE),
-        uuid16: 0x0002,
-        length: 1, // bool
-        user_description: "LED Write"
+use core::mem::size_of;
+
+use crate::app::{
+    auth::AUTH_BLOCK_LEN,
+    config::{
+        AdvertisingConfig, CalibrationCommand, IdleConfig, PostWriteConfig, TemperatureConfig,
+        TemperatureThresholds,
     },
-    {
-        etype: characteristic,
-        perm: perm!(RD, ENABLE),
-        uuid16: 0x0003,
-        length: 1, // bool
-        user_description: "LED Read"
+    history::{HistoryCommand, HISTORY_CHUNK_LEN},
+    led::LedPattern,
+    sensor::{SENSORS, SENSOR_COUNT, SENSOR_ENCODED_LEN},
+    temperature::TEMPERATURE_ENCODED_LEN,
+    unlock::UNLOCK_CREDENTIAL_LEN,
+};
+
+use super::{
+    service_db::{
+        characteristic, client_config, service, user_description, value, PERM_NTF_ENABLE,
     },
-    {
-        etype: characteristic,
-        perm: perm!(RD, ENABLE),
-        uuid16: 0x0004,
-        length: 2, // u16
-        user_description: "Temperature Read"
+    user_peripheral::custs1_value_wr_validation,
+};
+
+/// Custom service (Rapitag 16bit UUID)
+const SVC1_UUID: u16 = 0xFD6B;
+const SVC1_UNLOCK_UUID: u16 = 0x0001;
+const SVC1_LED_WRITE_UUID: u16 = 0x0002;
+const SVC1_LED_READ_UUID: u16 = 0x0003;
+const SVC1_TEMP_READ_UUID: u16 = 0x0004;
+const SVC1_ADV_CONFIG_UUID: u16 = 0x0005;
+const SVC1_UNLOCK_STATUS_UUID: u16 = 0x0006;
+const SVC1_AUTH_NONCE_UUID: u16 = 0x0007;
+const SVC1_AUTH_RESPONSE_UUID: u16 = 0x0008;
+const SVC1_POST_WRITE_CONFIG_UUID: u16 = 0x0009;
+const SVC1_IDLE_CONFIG_UUID: u16 = 0x000A;
+const SVC1_TEMP_CONFIG_UUID: u16 = 0x000B;
+const SVC1_TEMP_CALIBRATION_UUID: u16 = 0x000C;
+const SVC1_TEMP_HISTORY_UUID: u16 = 0x000D;
+const SVC1_TEMP_THRESHOLDS_UUID: u16 = 0x000E;
+const SVC1_TEMP_ALARM_UUID: u16 = 0x000F;
+/// The sensor characteristics count up from here in the order of `SENSORS` (up to 0x001F)
+const SVC1_SENSOR_BASE_UUID: u16 = 0x0010;
+const SVC1_LED_PATTERN_UUID: u16 = 0x0020;
+
+/// Battery Service
+const SVC2_UUID: u16 = 0x180F;
+const SVC2_BATTERY_LEVEL_UUID: u16 = 0x2A19;
+
+/// Number of entries in service 1 before the sensor characteristics
+pub(crate) const SVC1_FIXED_LEN: usize = 51;
+
+/// Number of entries of a sensor characteristic (declaration, value, client characteristic
+/// configuration, user description)
+pub(crate) const SENSOR_ATT_LEN: usize = 4;
+
+/// Index of the battery service declaration
+pub(crate) const SVC2_IDX: usize = SVC1_FIXED_LEN + SENSOR_COUNT * SENSOR_ATT_LEN;
+
+/// Number of entries in the battery service
+const SVC2_LEN: usize = 4;
+
+/// Number of entries in the service database
+pub(crate) const CUSTS1_ATT_DB_LEN: u8 = (SVC2_IDX + SVC2_LEN) as u8;
+
+// Setup service database, the indices are mirrored in `user_peripheral`
+#[export_name = "custs1_att_db"]
+pub(crate) static CUSTS1_ATT_DB: [AttmDesc128; CUSTS1_ATT_DB_LEN as usize] =
+    custs1_att_db(&SVC1_SENSOR_UUIDS);
+
+/// UUIDs of the sensor characteristics
+static SVC1_SENSOR_UUIDS: [u16; SENSOR_COUNT] = sensor_uuids();
+
+const fn sensor_uuids() -> [u16; SENSOR_COUNT] {
+    let mut uuids = [0; SENSOR_COUNT];
+    let mut index = 0;
+    while index < SENSOR_COUNT {
+        uuids[index] = SVC1_SENSOR_BASE_UUID + index as u16;
+        index += 1;
+    }
+
+    uuids
+}
+
+/// Append a read/notify characteristic for each of the `SENSORS` to service 1
+const fn custs1_att_db(
+    sensor_uuids: &'static [u16; SENSOR_COUNT],
+) -> [AttmDesc128; CUSTS1_ATT_DB_LEN as usize] {
+    let mut db = [characteristic(); CUSTS1_ATT_DB_LEN as usize];
+
+    let mut idx = 0;
+    while idx < SVC1_FIXED_LEN {
+        db[idx] = SVC1_ATT_DB[idx];
+        idx += 1;
     }
+
+    let mut sensor = 0;
+    while sensor < SENSOR_COUNT {
+        db[idx] = characteristic();
+        db[idx + 1] = value(
+            &sensor_uuids[sensor],
+            perm!(RD, ENABLE) | PERM_NTF_ENABLE,
+            SENSOR_ENCODED_LEN as u16,
+        );
+        db[idx + 2] = client_config();
+        db[idx + 3] = user_description(SENSORS[sensor].name.as_bytes());
+
+        idx += SENSOR_ATT_LEN;
+        sensor += 1;
+    }
+
+    let mut svc2_idx = 0;
+    while svc2_idx < SVC2_LEN {
+        db[idx] = SVC2_ATT_DB[svc2_idx];
+        idx += 1;
+        svc2_idx += 1;
+    }
+
+    db
+}
+
+/// Fixed entries of service 1
+const SVC1_ATT_DB: [AttmDesc128; SVC1_FIXED_LEN] = [
+    // 0
+    service(&SVC1_UUID),
+    // 1
+    characteristic(),
+    value(
+        &SVC1_UNLOCK_UUID,
+        perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        UNLOCK_CREDENTIAL_LEN as u16,
+    ),
+    user_description(b"Unlock"),
+    // 4
+    characteristic(),
+    value(
+        &SVC1_LED_WRITE_UUID,
+        perm!(WR, ENABLE) | perm!(WRITE_COMMAND, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        size_of::<bool>() as u16,
+    ),
+    user_description(b"LED Write"),
+    // 7
+    characteristic(),
+    value(&SVC1_LED_READ_UUID, perm!(RD, ENABLE), size_of::<bool>() as u16),
+    user_description(b"LED Read"),
+    // 10
+    characteristic(),
+    value(
+        &SVC1_TEMP_READ_UUID,
+        perm!(RD, ENABLE) | PERM_NTF_ENABLE,
+        TEMPERATURE_ENCODED_LEN as u16,
+    ),
+    client_config(),
+    user_description(b"Temperature Read"),
+    // 14
+    characteristic(),
+    value(
+        &SVC1_ADV_CONFIG_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        AdvertisingConfig::ENCODED_LEN as u16,
+    ),
+    user_description(b"Advertising Config"),
+    // 17
+    characteristic(),
+    value(
+        &SVC1_UNLOCK_STATUS_UUID,
+        perm!(RD, ENABLE),
+        size_of::<u8>() as u16, // UnlockStatus
+    ),
+    user_description(b"Unlock Status"),
+    // 20
+    characteristic(),
+    value(&SVC1_AUTH_NONCE_UUID, perm!(RD, ENABLE), AUTH_BLOCK_LEN as u16),
+    user_description(b"Auth Nonce"),
+    // 23
+    characteristic(),
+    value(
+        &SVC1_AUTH_RESPONSE_UUID,
+        perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        AUTH_BLOCK_LEN as u16,
+    ),
+    user_description(b"Auth Response"),
+    // 26
+    characteristic(),
+    value(
+        &SVC1_POST_WRITE_CONFIG_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        PostWriteConfig::ENCODED_LEN as u16,
+    ),
+    user_description(b"Post-Write Policy"),
+    // 29
+    characteristic(),
+    value(
+        &SVC1_IDLE_CONFIG_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        IdleConfig::ENCODED_LEN as u16,
+    ),
+    user_description(b"Idle Timeout"),
+    // 32
+    characteristic(),
+    value(
+        &SVC1_TEMP_CONFIG_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        TemperatureConfig::ENCODED_LEN as u16,
+    ),
+    user_description(b"Temperature Config"),
+    // 35
+    characteristic(),
+    value(
+        &SVC1_TEMP_CALIBRATION_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        CalibrationCommand::MAX_ENCODED_LEN as u16,
+    ),
+    user_description(b"Temperature Calibration"),
+    // 38
+    characteristic(),
+    value(
+        &SVC1_TEMP_HISTORY_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        HISTORY_CHUNK_LEN as u16, // commands are shorter
+    ),
+    user_description(b"Temperature History"),
+    // 41
+    characteristic(),
+    value(
+        &SVC1_TEMP_THRESHOLDS_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        TemperatureThresholds::ENCODED_LEN as u16,
+    ),
+    user_description(b"Temperature Thresholds"),
+    // 44
+    characteristic(),
+    value(
+        &SVC1_TEMP_ALARM_UUID,
+        perm!(RD, ENABLE) | PERM_NTF_ENABLE,
+        size_of::<u8>() as u16, // TemperatureLevel
+    ),
+    client_config(),
+    user_description(b"Temperature Alarm"),
+    // 48
+    characteristic(),
+    value(
+        &SVC1_LED_PATTERN_UUID,
+        perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        LedPattern::MAX_ENCODED_LEN as u16,
+    ),
+    user_description(b"LED Pattern"),
 ];
 
+// The temperature history characteristic takes both, commands and chunks
+const _: () = assert!(HistoryCommand::MAX_ENCODED_LEN <= HISTORY_CHUNK_LEN);
+
+/// Whether entry `idx` of service 1 is a characteristic value right after its declaration, checks
+/// the indices mirrored in `user_peripheral`
+pub(crate) const fn is_svc1_value(idx: u16) -> bool {
+    let idx = idx as usize;
+    idx > 0
+        && idx < SVC1_FIXED_LEN
+        && SVC1_ATT_DB[idx - 1].max_length == 0
+        && SVC1_ATT_DB[idx].max_length & perm!(RI, ENABLE) as u16 != 0
+}
+
+/// Whether entry `idx` of service 1 is the client characteristic configuration of the value
+/// before it
+pub(crate) const fn is_svc1_client_config(idx: u16) -> bool {
+    is_svc1_value(idx - 1)
+        && (idx as usize) < SVC1_FIXED_LEN
+        && SVC1_ATT_DB[idx as usize].max_length == size_of::<u16>() as u16
+}
+
+/// Battery service, follows the sensor characteristics
+const SVC2_ATT_DB: [AttmDesc128; SVC2_LEN] = [
+    // SVC2_IDX
+    service(&SVC2_UUID),
+    // SVC2_IDX + 1
+    characteristic(),
+    value(
+        &SVC2_BATTERY_LEVEL_UUID,
+        perm!(RD, ENABLE) | PERM_NTF_ENABLE,
+        size_of::<u8>() as u16, // level in %
+    ),
+    client_config(),
+];
+
+/// Indices of the service declarations, terminated by the number of entries
+#[export_name = "custs1_services"]
+static CUSTS1_SERVICES: [u8; 3] = [0, SVC2_IDX as u8, CUSTS1_ATT_DB_LEN];
+
+#[export_name = "custs1_services_size"]
+static CUSTS1_SERVICES_SIZE: u32 = CUSTS1_SERVICES.len() as u32 - 1;
+
+#[export_name = "rom_cust_prf_cfg"]
+static ROM_CUST_PRF_CFG: RomCustPrfCfg = RomCustPrfCfg {
+    custs1_services: CUSTS1_SERVICES.as_ptr(),
+    custs1_services_size: &(CUSTS1_SERVICES.len() as u8 - 1),
+    custs1_att_db: CUSTS1_ATT_DB.as_ptr() as *mut _,
+    custs_get_func_callbacks: Some(custs_get_func_callbacks),
+};
+
 /// Setup custom profile funcs
 #[no_mangle]
 pub static CUST_PRF_FUNCS: [CustPrfFuncCallbacks; 1] = [CustPrfFuncCallbacks {
@@ -46,11 +315,12 @@ pub static CUST_PRF_FUNCS: [CustPrfFuncCallbacks; 1] = [CustPrfFuncCallbacks {
     db_create_func: Some(app_custs1_create_db),
     enable_func: None,
     init_func: None,
-    value_wr_validation_func: None,
+    value_wr_validation_func: Some(custs1_value_wr_validation),
 }];
 
-/// Set the advertisement period
-const ADV_PERIOD: i32 = ms_to_timer_units(4000) as i32;
+/// Set the advertisement period (the app passes the configured period on every start)
+const ADV_PERIOD: i32 =
+    ms_to_timer_units(AdvertisingConfig::DEFAULT.period().as_millis() as u32) as i32;
 
 // Configure default handlers
 default_handlers_configuration! {
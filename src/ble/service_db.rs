@@ -0,0 +1,74 @@
+use da14531_sdk::{
+    bindings::{attm_perm_mask_PERM_MASK_NTF, attm_perm_mask_PERM_POS_NTF},
+    ble_stack::host::att::{
+        attm::{AttmDesc128, PERM_RIGHT_ENABLE},
+        ATT_DECL_CHARACTERISTIC, ATT_DECL_PRIMARY_SERVICE, ATT_DESC_CHAR_USER_DESCRIPTION,
+        ATT_DESC_CLIENT_CHAR_CFG, ATT_UUID_16_LEN,
+    },
+    perm,
+};
+
+/// Notifications enabled (not covered by `perm!`)
+pub const PERM_NTF_ENABLE: u32 =
+    (PERM_RIGHT_ENABLE << attm_perm_mask_PERM_POS_NTF) & attm_perm_mask_PERM_MASK_NTF;
+
+/// Primary service declaration
+pub const fn service(uuid: &'static u16) -> AttmDesc128 {
+    AttmDesc128 {
+        uuid: &ATT_DECL_PRIMARY_SERVICE as *const _ as *const u8,
+        uuid_size: ATT_UUID_16_LEN as u8,
+        perm: perm!(RD, ENABLE),
+        max_length: ATT_UUID_16_LEN as u16,
+        length: ATT_UUID_16_LEN as u16,
+        value: uuid as *const _ as *const u8,
+    }
+}
+
+/// Characteristic declaration, the stack derives it from the following value entry
+pub const fn characteristic() -> AttmDesc128 {
+    AttmDesc128 {
+        uuid: &ATT_DECL_CHARACTERISTIC as *const _ as *const u8,
+        uuid_size: ATT_UUID_16_LEN as u8,
+        perm: perm!(RD, ENABLE),
+        max_length: 0,
+        length: 0,
+        value: core::ptr::null(),
+    }
+}
+
+/// Characteristic value, reads are forwarded to the app (`CUSTS1_VALUE_REQ_IND`)
+pub const fn value(uuid: &'static u16, perm: u32, length: u16) -> AttmDesc128 {
+    AttmDesc128 {
+        uuid: uuid as *const _ as *const u8,
+        uuid_size: ATT_UUID_16_LEN as u8,
+        perm,
+        max_length: perm!(RI, ENABLE) as u16 | length,
+        length: 0,
+        value: core::ptr::null(),
+    }
+}
+
+/// Characteristic user description
+pub const fn user_description(description: &'static [u8]) -> AttmDesc128 {
+    AttmDesc128 {
+        uuid: &ATT_DESC_CHAR_USER_DESCRIPTION as *const _ as *const u8,
+        uuid_size: ATT_UUID_16_LEN as u8,
+        perm: perm!(RD, ENABLE),
+        max_length: description.len() as u16,
+        length: description.len() as u16,
+        value: description.as_ptr(),
+    }
+}
+
+/// Client characteristic configuration descriptor, writes are forwarded to the app
+/// (`CUSTS1_VAL_WRITE_IND`)
+pub const fn client_config() -> AttmDesc128 {
+    AttmDesc128 {
+        uuid: &ATT_DESC_CLIENT_CHAR_CFG as *const _ as *const u8,
+        uuid_size: ATT_UUID_16_LEN as u8,
+        perm: perm!(RD, ENABLE) | perm!(WR, ENABLE) | perm!(WRITE_REQ, ENABLE),
+        max_length: core::mem::size_of::<u16>() as u16,
+        length: 0,
+        value: core::ptr::null(),
+    }
+}
@@ -1,27 +1,165 @@
 use da14531_sdk::{
     app_modules::app_env_get_conidx,
     ble_stack::{
-        host::gap::gapc::task::GAPC_PARAM_UPDATED_IND,
+        host::gap::gapc::task::{GapcParamUpdatedInd, GAPC_PARAM_UPDATED_IND},
         profiles::custom::custs::custs1::task::{
             Custs1AttInfoReq, Custs1ValWriteInd, Custs1ValueReqInd, KeMsgCusts1AttInfoRsp,
             KeMsgCusts1ValueReqRsp, CUSTS1_ATT_INFO_REQ, CUSTS1_VALUE_REQ_IND,
             CUSTS1_VAL_WRITE_IND,
         },
-        rwble_hl::error::HlErr::{ATT_ERR_APP_ERROR, ATT_ERR_WRITE_NOT_PERMITTED},
+        rwble_hl::error::HlErr::{
+            ATT_ERR_APP_ERROR, ATT_ERR_WRITE_NOT_PERMITTED, GAP_ERR_NO_ERROR as ATT_ERR_NO_ERROR,
+        },
     },
     platform::core_modules::ke::{msg::KeMsgId, task::KeTaskId},
 };
+use rtt_target::rprintln;
+
+use crate::{
+    app::{conn_params::ConnectionParams, connection::Notification, event::AppEvent},
+    app_impl::{push_event, with_app},
+};
+
+use super::config::{
+    is_svc1_client_config, is_svc1_value, SENSOR_ATT_LEN, SVC1_FIXED_LEN, SVC2_IDX,
+};
 
 use super::char_handlers::{
-    led_read_char_read_handler, led_write_char_write_handler, temp_read_char_read_handler,
+    adv_config_char_read_handler, adv_config_char_validate, adv_config_char_write_handler,
+    auth_nonce_char_read_handler, auth_response_char_validate, auth_response_char_write_handler,
+    battery_level_char_read_handler, client_config_write_handler, idle_config_char_read_handler,
+    idle_config_char_validate, idle_config_char_write_handler, led_pattern_char_read_handler,
+    led_pattern_char_validate, led_pattern_char_write_handler, led_read_char_read_handler,
+    led_write_char_write_handler, post_write_config_char_read_handler,
+    post_write_config_char_validate, post_write_config_char_write_handler,
+    sensor_char_read_handler, temp_alarm_char_read_handler, temp_calibration_char_read_handler,
+    temp_calibration_char_validate, temp_calibration_char_write_handler,
+    temp_config_char_read_handler, temp_config_char_validate, temp_config_char_write_handler,
+    temp_history_char_read_handler, temp_history_char_validate, temp_history_char_write_handler,
+    temp_read_char_read_handler, temp_thresholds_char_read_handler, temp_thresholds_char_validate,
+    temp_thresholds_char_write_handler, unlock_char_validate, unlock_char_write_handler,
+    unlock_status_char_read_handler,
 };
 
 // This whole thing needs to be simplified with macros!!
 // These are the indices of the entries in the service database
+// (service: 1 entry, characteristic: 3 entries incl. user description, +1 with notifications)
 const SVC1_IDX_UNLOCK_VAL: u16 = 2;
-const SVC1_IDX_LED_READ_VAL: u16 = 5;
-const SVC1_IDX_LED_WRITE_VAL: u16 = 8;
+const SVC1_IDX_LED_WRITE_VAL: u16 = 5;
+const SVC1_IDX_LED_READ_VAL: u16 = 8;
 const SVC1_IDX_TEMP_READ_VAL: u16 = 11;
+const SVC1_IDX_TEMP_READ_NTF_CFG: u16 = 12;
+const SVC1_IDX_ADV_CONFIG_VAL: u16 = 15;
+const SVC1_IDX_UNLOCK_STATUS_VAL: u16 = 18;
+const SVC1_IDX_AUTH_NONCE_VAL: u16 = 21;
+const SVC1_IDX_AUTH_RESPONSE_VAL: u16 = 24;
+const SVC1_IDX_POST_WRITE_CONFIG_VAL: u16 = 27;
+const SVC1_IDX_IDLE_CONFIG_VAL: u16 = 30;
+const SVC1_IDX_TEMP_CONFIG_VAL: u16 = 33;
+const SVC1_IDX_TEMP_CALIBRATION_VAL: u16 = 36;
+const SVC1_IDX_TEMP_HISTORY_VAL: u16 = 39;
+const SVC1_IDX_TEMP_THRESHOLDS_VAL: u16 = 42;
+const SVC1_IDX_TEMP_ALARM_VAL: u16 = 45;
+const SVC1_IDX_TEMP_ALARM_NTF_CFG: u16 = 46;
+const SVC1_IDX_LED_PATTERN_VAL: u16 = 49;
+/// Declaration of the first sensor characteristic, the others follow every `SENSOR_ATT_LEN`
+const SVC1_IDX_SENSORS: u16 = SVC1_FIXED_LEN as u16;
+/// Offsets in the entries of a sensor characteristic
+const SENSOR_OFFSET_VAL: u16 = 1;
+const SENSOR_OFFSET_NTF_CFG: u16 = 2;
+
+// Fail the build if the indices went out of sync with `SVC1_ATT_DB`
+const _: () = {
+    assert!(is_svc1_value(SVC1_IDX_UNLOCK_VAL));
+    assert!(is_svc1_value(SVC1_IDX_LED_WRITE_VAL));
+    assert!(is_svc1_value(SVC1_IDX_LED_READ_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_READ_VAL));
+    assert!(is_svc1_client_config(SVC1_IDX_TEMP_READ_NTF_CFG));
+    assert!(is_svc1_value(SVC1_IDX_ADV_CONFIG_VAL));
+    assert!(is_svc1_value(SVC1_IDX_UNLOCK_STATUS_VAL));
+    assert!(is_svc1_value(SVC1_IDX_AUTH_NONCE_VAL));
+    assert!(is_svc1_value(SVC1_IDX_AUTH_RESPONSE_VAL));
+    assert!(is_svc1_value(SVC1_IDX_POST_WRITE_CONFIG_VAL));
+    assert!(is_svc1_value(SVC1_IDX_IDLE_CONFIG_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_CONFIG_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_CALIBRATION_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_HISTORY_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_THRESHOLDS_VAL));
+    assert!(is_svc1_value(SVC1_IDX_TEMP_ALARM_VAL));
+    assert!(is_svc1_client_config(SVC1_IDX_TEMP_ALARM_NTF_CFG));
+    assert!(is_svc1_value(SVC1_IDX_LED_PATTERN_VAL));
+    // The LED pattern is followed by its user description only
+    assert!(SVC1_IDX_LED_PATTERN_VAL as usize + 2 == SVC1_FIXED_LEN);
+};
+
+const SVC2_IDX_BATTERY_LEVEL_VAL: u16 = SVC2_IDX as u16 + 2;
+const SVC2_IDX_BATTERY_LEVEL_NTF_CFG: u16 = SVC2_IDX as u16 + 3;
+
+/// Get the sensor index and the offset in its entries of the attribute `att_idx`
+fn sensor_att(att_idx: u16) -> Option<(u8, u16)> {
+    if !(SVC1_IDX_SENSORS..SVC2_IDX as u16).contains(&att_idx) {
+        return None;
+    }
+
+    let offset = att_idx - SVC1_IDX_SENSORS;
+    let len = SENSOR_ATT_LEN as u16;
+    Some(((offset / len) as u8, offset % len))
+}
+
+/// Attribute index of the characteristic value behind `notification`
+pub fn notification_att_idx(notification: Notification) -> u16 {
+    match notification {
+        Notification::BatteryLevel => SVC2_IDX_BATTERY_LEVEL_VAL,
+        Notification::Temperature => SVC1_IDX_TEMP_READ_VAL,
+        Notification::TemperatureAlarm => SVC1_IDX_TEMP_ALARM_VAL,
+        Notification::Sensor(index) => {
+            SVC1_IDX_SENSORS + index as u16 * SENSOR_ATT_LEN as u16 + SENSOR_OFFSET_VAL
+        }
+    }
+}
+
+/// Check if writing the attribute requires an authenticated connection
+fn is_protected(att_idx: u16) -> bool {
+    matches!(
+        att_idx,
+        SVC1_IDX_LED_WRITE_VAL
+            | SVC1_IDX_ADV_CONFIG_VAL
+            | SVC1_IDX_POST_WRITE_CONFIG_VAL
+            | SVC1_IDX_IDLE_CONFIG_VAL
+            | SVC1_IDX_TEMP_CONFIG_VAL
+            | SVC1_IDX_TEMP_CALIBRATION_VAL
+            | SVC1_IDX_TEMP_HISTORY_VAL
+            | SVC1_IDX_TEMP_THRESHOLDS_VAL
+            | SVC1_IDX_LED_PATTERN_VAL
+    )
+}
+
+/// Validate writes before they are stored in the database, returns the ATT error code
+///
+/// The authentication of protected attributes is checked by the write indication, which
+/// carries the connection index.
+pub extern "C" fn custs1_value_wr_validation(
+    att_idx: u16,
+    _offset: u16,
+    len: u16,
+    value: *mut u8,
+) -> u8 {
+    let value = unsafe { core::slice::from_raw_parts(value, len as usize) };
+
+    match att_idx {
+        SVC1_IDX_UNLOCK_VAL => unlock_char_validate(value),
+        SVC1_IDX_AUTH_RESPONSE_VAL => auth_response_char_validate(value),
+        SVC1_IDX_ADV_CONFIG_VAL => adv_config_char_validate(value),
+        SVC1_IDX_POST_WRITE_CONFIG_VAL => post_write_config_char_validate(value),
+        SVC1_IDX_IDLE_CONFIG_VAL => idle_config_char_validate(value),
+        SVC1_IDX_TEMP_CONFIG_VAL => temp_config_char_validate(value),
+        SVC1_IDX_TEMP_CALIBRATION_VAL => temp_calibration_char_validate(value),
+        SVC1_IDX_TEMP_HISTORY_VAL => temp_history_char_validate(value),
+        SVC1_IDX_TEMP_THRESHOLDS_VAL => temp_thresholds_char_validate(value),
+        SVC1_IDX_LED_PATTERN_VAL => led_pattern_char_validate(value),
+        _ => ATT_ERR_NO_ERROR as u8,
+    }
+}
 
 #[no_mangle]
 pub fn user_catch_rest_hndl(
@@ -38,31 +176,94 @@ pub fn user_catch_rest_hndl(
     //     src_id
     // );
     if msg_id == GAPC_PARAM_UPDATED_IND as u16 {
-        // let param = param as *const GapcParamUpdatedInd;
-        // let param = unsafe { &*param };
-        // let con_interval = param.con_interval;
-        // let con_latency = param.con_latency;
-        // let sup_to = param.sup_to;
-
-        // // Check if updated Conn Params filled to preferred ones
-        // if (con_interval >= USER_CONNECTION_PARAM_CONF.intv_min)
-        //     && (con_interval <= USER_CONNECTION_PARAM_CONF.intv_max)
-        //     && (con_latency == USER_CONNECTION_PARAM_CONF.latency)
-        //     && (sup_to == USER_CONNECTION_PARAM_CONF.time_out)
-        // {}
+        let param = param as *const GapcParamUpdatedInd;
+        let param = unsafe { &*param };
+
+        // The indication is sent by the GAPC task of the connection (`KE_IDX_GET(src_id)`)
+        let conidx = (src_id >> 8) as u8;
+
+        // The app compares them with the preferred ones
+        push_event(AppEvent::ConnParamsUpdated(
+            conidx,
+            ConnectionParams {
+                interval: param.con_interval,
+                latency: param.con_latency,
+                supervision_timeout: param.sup_to,
+            },
+        ));
 
         return;
     }
 
+    // GATT accesses call the app directly instead of queuing an event, see `with_app`
     match msg_id as u32 {
         CUSTS1_VAL_WRITE_IND => {
             let param = param as *const Custs1ValWriteInd;
             let param = unsafe { &*param };
+            let authenticated = with_app(|app| {
+                app.on_activity(param.conidx);
+                app.is_authenticated(param.conidx)
+            });
+
+            // The value is only taken from the indication, so dropping it rolls back the write
+            // (the copy in the database is never read)
+            if is_protected(param.handle) && !authenticated {
+                rprintln!(
+                    "Write of {} by unauthenticated connection {} dropped",
+                    param.handle,
+                    param.conidx
+                );
+                return;
+            }
+
             match param.handle {
+                SVC1_IDX_UNLOCK_VAL => {
+                    unlock_char_write_handler(param);
+                }
                 SVC1_IDX_LED_WRITE_VAL => {
                     led_write_char_write_handler(param);
                 }
-                _ => {}
+                SVC1_IDX_ADV_CONFIG_VAL => {
+                    adv_config_char_write_handler(param);
+                }
+                SVC1_IDX_AUTH_RESPONSE_VAL => {
+                    auth_response_char_write_handler(param);
+                }
+                SVC1_IDX_POST_WRITE_CONFIG_VAL => {
+                    post_write_config_char_write_handler(param);
+                }
+                SVC1_IDX_IDLE_CONFIG_VAL => {
+                    idle_config_char_write_handler(param);
+                }
+                SVC1_IDX_TEMP_CONFIG_VAL => {
+                    temp_config_char_write_handler(param);
+                }
+                SVC1_IDX_TEMP_CALIBRATION_VAL => {
+                    temp_calibration_char_write_handler(param);
+                }
+                SVC1_IDX_TEMP_HISTORY_VAL => {
+                    temp_history_char_write_handler(param);
+                }
+                SVC1_IDX_TEMP_THRESHOLDS_VAL => {
+                    temp_thresholds_char_write_handler(param);
+                }
+                SVC1_IDX_LED_PATTERN_VAL => {
+                    led_pattern_char_write_handler(param);
+                }
+                SVC1_IDX_TEMP_ALARM_NTF_CFG => {
+                    client_config_write_handler(param, Notification::TemperatureAlarm);
+                }
+                SVC1_IDX_TEMP_READ_NTF_CFG => {
+                    client_config_write_handler(param, Notification::Temperature);
+                }
+                SVC2_IDX_BATTERY_LEVEL_NTF_CFG => {
+                    client_config_write_handler(param, Notification::BatteryLevel);
+                }
+                handle => {
+                    if let Some((index, SENSOR_OFFSET_NTF_CFG)) = sensor_att(handle) {
+                        client_config_write_handler(param, Notification::Sensor(index));
+                    }
+                }
             }
         }
         CUSTS1_ATT_INFO_REQ => {
@@ -94,28 +295,44 @@ pub fn user_catch_rest_hndl(
         CUSTS1_VALUE_REQ_IND => {
             let param = param as *const Custs1ValueReqInd;
             let param = unsafe { &*param };
+            with_app(|app| app.on_activity(param.conidx));
             let att_idx = param.att_idx;
 
             match att_idx {
                 SVC1_IDX_LED_READ_VAL => led_read_char_read_handler(param),
                 SVC1_IDX_TEMP_READ_VAL => temp_read_char_read_handler(param),
-                _ => {
-                    let mut response = KeMsgCusts1ValueReqRsp::new(dest_id, src_id);
+                SVC1_IDX_ADV_CONFIG_VAL => adv_config_char_read_handler(param),
+                SVC1_IDX_UNLOCK_STATUS_VAL => unlock_status_char_read_handler(param),
+                SVC1_IDX_AUTH_NONCE_VAL => auth_nonce_char_read_handler(param),
+                SVC1_IDX_POST_WRITE_CONFIG_VAL => post_write_config_char_read_handler(param),
+                SVC1_IDX_IDLE_CONFIG_VAL => idle_config_char_read_handler(param),
+                SVC1_IDX_TEMP_CONFIG_VAL => temp_config_char_read_handler(param),
+                SVC1_IDX_TEMP_CALIBRATION_VAL => temp_calibration_char_read_handler(param),
+                SVC1_IDX_TEMP_HISTORY_VAL => temp_history_char_read_handler(param),
+                SVC1_IDX_TEMP_THRESHOLDS_VAL => temp_thresholds_char_read_handler(param),
+                SVC1_IDX_TEMP_ALARM_VAL => temp_alarm_char_read_handler(param),
+                SVC1_IDX_LED_PATTERN_VAL => led_pattern_char_read_handler(param),
+                SVC2_IDX_BATTERY_LEVEL_VAL => battery_level_char_read_handler(param),
+                _ => match sensor_att(att_idx) {
+                    Some((index, SENSOR_OFFSET_VAL)) => sensor_char_read_handler(param, index),
+                    _ => {
+                        let mut response = KeMsgCusts1ValueReqRsp::new(dest_id, src_id);
 
-                    // Provide the connection index.
-                    response.fields().conidx = app_env_get_conidx(param.conidx);
+                        // Provide the connection index.
+                        response.fields().conidx = app_env_get_conidx(param.conidx);
 
-                    // Provide the attribute index.
-                    response.fields().att_idx = param.att_idx;
+                        // Provide the attribute index.
+                        response.fields().att_idx = param.att_idx;
 
-                    // Force current length to zero.
-                    response.fields().length = 0;
+                        // Force current length to zero.
+                        response.fields().length = 0;
 
-                    // Provide the ATT error code.
-                    response.fields().status = ATT_ERR_APP_ERROR as u8;
+                        // Provide the ATT error code.
+                        response.fields().status = ATT_ERR_APP_ERROR as u8;
 
-                    response.send();
-                }
+                        response.send();
+                    }
+                },
             }
         }
         _ => {}
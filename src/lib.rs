@@ -2,49 +2,62 @@
 //! 
 //! This project contains a simple BLE application, which can control an LED and read the die temperature
 
-#![no_std]
-#![feature(default_alloc_error_handler)]
+#![cfg_attr(not(feature = "sim"), no_std)]
+#![cfg_attr(not(feature = "sim"), feature(default_alloc_error_handler))]
 
 extern crate alloc;
 
+#[cfg(not(feature = "sim"))]
 use core::{
     panic::PanicInfo,
     sync::atomic::{self, Ordering},
 };
 
+#[cfg(not(feature = "sim"))]
 use da14531_sdk::allocator::Da14531Allocator;
 
 /// The actual application code and definition of interfaces for peripheral and BLE drivers
 pub mod app;
 /// Glue between SDK system and application code
+#[cfg(not(feature = "sim"))]
 pub mod app_impl;
 /// BLE
+#[cfg(not(feature = "sim"))]
 pub mod ble;
 /// HAL for peripherals
+#[cfg(not(feature = "sim"))]
 pub mod peripherals;
+/// Timers
+#[cfg(not(feature = "sim"))]
+pub mod timer;
+/// In-memory drivers to run the application code on the host
+#[cfg(feature = "sim")]
+pub mod sim;
 
 /// Global allocator (Needed to use heap, eg. for `Vec<T>`)
+#[cfg(not(feature = "sim"))]
 #[global_allocator]
 static ALLOCATOR: Da14531Allocator = Da14531Allocator;
 
 /// Panic handler in debug builds
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, not(feature = "sim")))]
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     use rtt_target::rprintln;
 
-    use crate::app_impl::app;
+    use crate::app_impl::try_with_app;
 
     rprintln!("Panic!");
     loop {
         atomic::compiler_fence(Ordering::SeqCst);
-        app().feed_watchdog();
+        // If the panic happened while the app was borrowed, let the watchdog reset the chip
+        let _ = try_with_app(|app| app.feed_watchdog());
     }
 }
 
 /// Panic handler in release builds
-#[cfg(not(debug_assertions))]
+#[cfg(all(not(debug_assertions), not(feature = "sim")))]
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
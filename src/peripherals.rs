@@ -8,12 +8,12 @@ use da14531_hal::{
     crg_top::{CrgTop, CrgTopExt},
     gpadc::{
         config::{
-            AdcConfig, AdcInputTemp, AdcInputVbatHigh, AdcInputVbatLow, AdcInputVddd, Attenuation,
-            Averaging, Chopper, SampleTime,
+            AdcConfig, AdcInputPositive, AdcInputTemp, AdcInputVbatHigh, AdcInputVbatLow,
+            AdcInputVddd, Attenuation, Averaging, Chopper, SampleTime,
         },
         GpAdc, GpAdcExt,
     },
-    gpio::{p0::Parts, Output, Pin},
+    gpio::{p0::{Parts, P0_06, P0_07}, AfAdc, Output, Pin},
     hal::{adc::Channel, digital::v2::{OutputPin, PinState}},
     i2c::I2cExt,
     nvic::{Irq, Nvic, NvicExt},
@@ -21,20 +21,42 @@ use da14531_hal::{
     sys_wdog::{SysWdog, SysWdogExt},
     timer::{BaseClockDiv, Timer0, Timer0Ext},
 };
-use da14531_sdk::platform::{
-    driver::syscntl::{dcdc_turn_on_in_boost, SyscntlDcdcLevel::SYSCNTL_DCDC_LEVEL_3V0},
-    system_library::patch_func,
+use da14531_sdk::{
+    platform::{
+        core_modules::crypto::{aes_operation_sync, AesOperation, AesResult},
+        driver::syscntl::{dcdc_turn_on_in_boost, SyscntlDcdcLevel::SYSCNTL_DCDC_LEVEL_3V0},
+        utilities::otp_cs::{otp_cs_get_adc_25_cal, otp_cs_get_adc_trim_val},
+        system_library::patch_func,
+    },
 };
 use rtt_target::rprintln;
 
-use crate::{
-    app::{PeripheralsDriver, Sound},
-    app_impl::app,
+use crate::app::{
+    adc::{self, AdcAttenuation, AdcAveraging, AdcChannel, AdcSampleTime, AdcSettings},
+    auth::{AuthBlock, DeviceKey},
+    battery::BatteryType,
+    filter::{AdcFilters, Ema, Filter, Median, MovingAverage, OutlierRejection},
+    led::LedPattern,
+    persistent::PersistentSettings,
+    retained::RetainedState,
+    sensor::is_sensor_channel,
+    unlock::UnlockCredential,
+    PeripheralsDriver, Sound,
 };
 
-use self::audio::Audio;
+use self::{audio::Audio, flash::Flash, led::Led};
 
 mod audio;
+mod flash;
+mod led;
+mod persistent;
+mod retained;
+
+extern "C" {
+    /// Fill `trng_bits_ptr` with `random_bytes_num` (a multiple of 4) random bytes, the TRNG
+    /// samples the radio, so it has to run before the BLE stack is started (SDK `trng.c`)
+    fn trng_acquire(trng_bits_ptr: *mut u8, random_bytes_num: u32);
+}
 
 /// This struct contains all relevant peripherals and implements the `PeripheralsDriver` trait
 pub struct Da14531Peripherals {
@@ -50,14 +72,37 @@ pub struct Da14531Peripherals {
     /// Used for PWM0 (audio)
     pwm_timer: Timer0,
 
-    /// Temperature ADC
+    /// GPADC for the die temperature, supply voltages and external inputs
     adc: GpAdc,
 
+    /// External ADC inputs used by `SENSORS`, held to keep them in their analog function
+    analog_pins: (Option<P0_06<AfAdc>>, Option<P0_07<AfAdc>>),
+
+    /// Value of `GP_ADC_TRIM_REG` from the OTP configuration script, 0 if not calibrated
+    adc_trim: u16,
+
+    /// Sample of the die temperature sensor at 25 °C from the OTP configuration script
+    temperature_reference: u16,
+
+    /// Filter pipelines of the ADC channels (Not used by interrupts, so a `RefCell` is enough)
+    adc_filters: RefCell<AdcFilters>,
+
     /// PWM piezo peripheral (In `Mutex<...>` since it needs to be interrupt safe)
     audio: Mutex<RefCell<Audio>>,
 
     /// LED pin
     led_pin: Pin<Output>,
+
+    /// LED pattern player
+    led: Led,
+
+    /// SPI flash, which holds the persistent settings and the device key
+    flash: Flash,
+
+    /// Key of the random number generator, taken from the TRNG at startup
+    random_key: AuthBlock,
+    /// Counter encrypted by the random number generator
+    random_counter: u128,
 }
 
 impl Da14531Peripherals {
@@ -84,18 +129,57 @@ impl Da14531Peripherals {
         // Enable pad latch
         crg_aon.set_pad_latch_en(true);
 
+        // `periph_init` runs before the BLE stack is started, so the TRNG is still available
+        let mut random_key = [0; 16];
+        unsafe { trng_acquire(random_key.as_mut_ptr(), random_key.len() as u32) };
+
         // Setup pins
-        let _spi_flash_en = p0.p0_01.into_output(PinState::High); // Disallow spontaneous SPI Flash wake-up
+        let flash = Flash::new(
+            p0.p0_01.degrade().into_output(PinState::High), // CS, high disallows spontaneous wake-up
+            p0.p0_04.degrade().into_output(PinState::Low), // CLK
+            p0.p0_00.degrade().into_output(PinState::Low), // DI of the flash
+            p0.p0_03.degrade().into_floating_input(), // DO of the flash
+        );
         let wakeup_pin = p0.p0_05.into_floating_input();
         let pwm_buzzer = p0.p0_11.degrade().into_alternate();
         let led_pin = p0.p0_08.degrade().into_output(PinState::Low);
+        // P0_1 and P0_2 are analog capable too, but taken by the SPI flash enable and SWCLK
+        let analog_pins = (
+            is_sensor_channel(AdcChannel::P0_6).then(|| p0.p0_06.into_alternate()),
+            is_sensor_channel(AdcChannel::P0_7).then(|| p0.p0_07.into_alternate()),
+        );
 
         pwm_timer.enable_clock();
         pwm_timer.set_clock_div(BaseClockDiv::Div8);
 
         Self::audio_init(pwm_buzzer, &mut pwm_timer, &mut nvic);
 
-        let sleep_config = SleepConfig::default().enable_pin(wakeup_pin);
+        // Keep RAM3 powered, it holds the retained app state
+        let sleep_config = SleepConfig::default()
+            .enable_pin(wakeup_pin)
+            .set_ram_power(false, false, true);
+
+        // Calibration of the production test, both values are 0 if the OTP holds none
+        let adc_trim = otp_cs_get_adc_trim_val();
+        let temperature_reference = match otp_cs_get_adc_25_cal() {
+            0 => adc::TEMP_SAMPLE_AT_25C,
+            sample => sample,
+        };
+
+        let mut adc_filters = AdcFilters::default();
+        // Drop spikes of more than 5°C (92.8 LSB/°C), unless they last for 3 samples
+        adc_filters.set(
+            AdcChannel::Temp,
+            Some(Box::new((OutlierRejection::new(464, 2), Median::<3>::new()))),
+        );
+        // The cell voltage sags while the radio or the buzzer is active
+        adc_filters.set(AdcChannel::VbatHigh, Some(Box::new(MovingAverage::<4>::new())));
+        adc_filters.set(AdcChannel::VbatLow, Some(Box::new(MovingAverage::<4>::new())));
+        adc_filters.set(AdcChannel::Vddd, Some(Box::new(MovingAverage::<4>::new())));
+        // The light sensor on P0_7 flickers with mains powered lamps
+        if analog_pins.1.is_some() {
+            adc_filters.set(AdcChannel::P0_7, Some(Box::new(Ema::new(1 << 14))));
+        }
 
         Da14531Peripherals {
             sys_wdog,
@@ -105,9 +189,17 @@ impl Da14531Peripherals {
             sleep_config,
             scb,
             adc,
+            analog_pins,
+            adc_trim,
+            temperature_reference,
+            adc_filters: RefCell::new(adc_filters),
             pwm_timer,
             led_pin,
+            led: Led::new(),
             audio: Mutex::new(RefCell::new(Audio::new())),
+            flash,
+            random_key,
+            random_counter: 0,
         }
     }
 }
@@ -138,27 +230,54 @@ impl PeripheralsDriver for Da14531Peripherals {
         );
     }
 
-    /// Get die temperature in milli °C (28230 = 28.23°C)
-    fn get_temperature(&self) -> u16 {
-        self.adc.init(
-            AdcConfig::default()
-                .set_channel_pos(AdcInputTemp)
-                .set_chopper_mode(Chopper::On)
-                .set_sample_time(SampleTime::Cycles15X8)
-                .set_averaging(Averaging::SamplesX32),
-        );
+    /// Take a single GPADC sample, the chopper is always on
+    fn sample_adc(&self, settings: &AdcSettings) -> Option<u16> {
+        let config = AdcConfig::default()
+            .set_attenuation(attenuation(settings.attenuation))
+            .set_chopper_mode(Chopper::On)
+            .set_sample_time(sample_time(settings.sample_time))
+            .set_averaging(averaging(settings.averaging));
+
+        let config = match settings.channel {
+            AdcChannel::P0_6 if self.analog_pins.0.is_some() => {
+                config.set_channel_pos(AdcInputPin::<2>)
+            }
+            AdcChannel::P0_7 if self.analog_pins.1.is_some() => {
+                config.set_channel_pos(AdcInputPin::<3>)
+            }
+            // Pins not used by `SENSORS` are not in their analog function
+            AdcChannel::P0_1 | AdcChannel::P0_2 | AdcChannel::P0_6 | AdcChannel::P0_7 => {
+                return None
+            }
+            AdcChannel::Temp => config.set_channel_pos(AdcInputTemp),
+            AdcChannel::VbatHigh => config.set_channel_pos(AdcInputVbatHigh),
+            AdcChannel::VbatLow => config.set_channel_pos(AdcInputVbatLow),
+            AdcChannel::Vddd => config.set_channel_pos(AdcInputVddd),
+        };
 
+        self.adc.init(config);
+        // `init` sets the nominal LDO level, the trimmed one matches the nominal full scale
+        if self.adc_trim != 0 {
+            unsafe { (*GPADC::ptr()).gp_adc_trim_reg.write(|w| w.bits(self.adc_trim)) };
+        }
         self.adc.start_conversion();
         self.adc.wait_for_conversion();
         let result = self.adc.current_sample();
-        let temp =
-            (25000 + ((result as i32 - 30272i32) as f32 / (1.45 * 64.0) * 1000.0) as i32) as u16;
-        rprintln!("AdcInputTemp value: {}", result);
-        rprintln!("AdcInputTemp temp: {} m°C", temp);
-
         self.adc.disable();
 
-        temp
+        Some(self.adc_filters.borrow_mut().apply(settings.channel, result))
+    }
+
+    fn temperature_reference(&self) -> u16 {
+        self.temperature_reference
+    }
+
+    fn set_adc_filter(&mut self, channel: AdcChannel, filter: Option<Box<dyn Filter>>) {
+        self.adc_filters.get_mut().set(channel, filter);
+    }
+
+    fn battery_type(&self) -> BatteryType {
+        BatteryType::Aaa
     }
 
     /// Feed the dog :)
@@ -173,4 +292,136 @@ impl PeripheralsDriver for Da14531Peripherals {
             false => PinState::Low,
         });
     }
+
+    /// Start a timer-driven LED pattern
+    fn play_led_pattern(&mut self, pattern: LedPattern) {
+        self.led_play_pattern(pattern);
+    }
+
+    /// Advance the current LED pattern
+    fn on_led_timer(&mut self) {
+        self.led_on_timer();
+    }
+
+    /// Advance the current sound
+    fn on_pwm_interrupt(&mut self) {
+        self.audio_on_pwm_interrupt();
+    }
+
+    /// Write state to RAM, which is kept during hibernation
+    fn store_retained(&mut self, state: &RetainedState) {
+        self.retained_store(state);
+    }
+
+    /// Read state stored before the last hibernation
+    fn load_retained(&mut self) -> Option<RetainedState> {
+        self.retained_load()
+    }
+
+    /// Write settings to the SPI flash
+    fn store_persistent(&mut self, settings: &PersistentSettings) {
+        self.persistent_store(settings);
+    }
+
+    /// Read the settings written to the SPI flash, before the last power loss
+    fn load_persistent(&mut self) -> Option<PersistentSettings> {
+        self.persistent_load()
+    }
+
+    /// Read the key provisioned in the SPI flash
+    fn load_device_key(&mut self) -> Option<DeviceKey> {
+        self.device_key_load()
+    }
+
+    /// Read the credential written in production next to the device key
+    fn load_unlock_credential(&mut self) -> Option<UnlockCredential> {
+        self.unlock_credential_load()
+    }
+
+    /// Fill buffer with random numbers: AES-128 of a counter with a key from the TRNG
+    ///
+    /// If the AES engine fails, the rest of the buffer is left as it is. The response to such
+    /// a nonce can't be checked either, since that needs the engine as well.
+    fn random_bytes(&mut self, buffer: &mut [u8]) {
+        let key = self.random_key;
+
+        for chunk in buffer.chunks_mut(16) {
+            self.random_counter = self.random_counter.wrapping_add(1);
+            let block = self.random_counter.to_le_bytes();
+            match self.aes128_encrypt(&key, &block) {
+                Some(random) => chunk.copy_from_slice(&random[..chunk.len()]),
+                None => return,
+            }
+        }
+    }
+
+    /// Encrypt a single block with the AES engine
+    fn aes128_encrypt(&mut self, key: &AuthBlock, block: &AuthBlock) -> Option<AuthBlock> {
+        let mut result = [0; 16];
+
+        match aes_operation_sync(key, block, &mut result, AesOperation::Encrypt) {
+            AesResult::Success => Some(result),
+            error => {
+                rprintln!("AES encryption failed: {:?}", error);
+                None
+            }
+        }
+    }
+}
+
+/// External pin on GPADC channel `CHANNEL`
+///
+/// The pins themselves can't be passed to `AdcConfig::set_channel_pos`, since they are kept by
+/// `Da14531Peripherals` in their analog function.
+struct AdcInputPin<const CHANNEL: u8>;
+
+impl<const CHANNEL: u8> Channel<GPADC> for AdcInputPin<CHANNEL> {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        CHANNEL
+    }
+}
+
+impl<const CHANNEL: u8> AdcInputPositive for AdcInputPin<CHANNEL> {}
+
+fn attenuation(attenuation: AdcAttenuation) -> Attenuation {
+    match attenuation {
+        AdcAttenuation::X1 => Attenuation::None,
+        AdcAttenuation::X2 => Attenuation::X2,
+        AdcAttenuation::X3 => Attenuation::X3,
+        AdcAttenuation::X4 => Attenuation::X4,
+    }
+}
+
+fn averaging(averaging: AdcAveraging) -> Averaging {
+    match averaging {
+        AdcAveraging::X1 => Averaging::SamplesX1,
+        AdcAveraging::X2 => Averaging::SamplesX2,
+        AdcAveraging::X4 => Averaging::SamplesX4,
+        AdcAveraging::X8 => Averaging::SamplesX8,
+        AdcAveraging::X16 => Averaging::SamplesX16,
+        AdcAveraging::X32 => Averaging::SamplesX32,
+        AdcAveraging::X128 => Averaging::SamplesX128,
+    }
+}
+
+fn sample_time(sample_time: AdcSampleTime) -> SampleTime {
+    match sample_time {
+        AdcSampleTime::Cycles1X8 => SampleTime::Cycles1X8,
+        AdcSampleTime::Cycles2X8 => SampleTime::Cycles2X8,
+        AdcSampleTime::Cycles3X8 => SampleTime::Cycles3X8,
+        AdcSampleTime::Cycles4X8 => SampleTime::Cycles4X8,
+        AdcSampleTime::Cycles5X8 => SampleTime::Cycles5X8,
+        AdcSampleTime::Cycles6X8 => SampleTime::Cycles6X8,
+        AdcSampleTime::Cycles7X8 => SampleTime::Cycles7X8,
+        AdcSampleTime::Cycles8X8 => SampleTime::Cycles8X8,
+        AdcSampleTime::Cycles9X8 => SampleTime::Cycles9X8,
+        AdcSampleTime::Cycles10X8 => SampleTime::Cycles10X8,
+        AdcSampleTime::Cycles11X8 => SampleTime::Cycles11X8,
+        AdcSampleTime::Cycles12X8 => SampleTime::Cycles12X8,
+        AdcSampleTime::Cycles13X8 => SampleTime::Cycles13X8,
+        AdcSampleTime::Cycles14X8 => SampleTime::Cycles14X8,
+        AdcSampleTime::Cycles15X8 => SampleTime::Cycles15X8,
+    }
 }
@@ -6,7 +6,10 @@ use da14531_hal::{
     timer::{ClockSel, PwmMode, Timer0, TimerClockDiv},
 };
 
-use crate::{app::Sound, app_impl::app};
+use crate::{
+    app::{event::AppEvent, Sound},
+    app_impl::push_event,
+};
 
 use super::Da14531Peripherals;
 
@@ -185,5 +188,5 @@ impl Da14531Peripherals {
 }
 
 fn pwm_handler() {
-    app().peripherals().audio_on_pwm_interrupt();
+    push_event(AppEvent::PwmInterrupt);
 }
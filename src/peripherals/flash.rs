@@ -0,0 +1,171 @@
+use da14531_hal::{
+    cm::asm::delay,
+    gpio::{Floating, Input, Output, Pin},
+    hal::digital::v2::{InputPin, OutputPin, PinState},
+    pac::CRG_AON,
+};
+
+/// Size of an erasable sector
+pub(super) const FLASH_SECTOR_SIZE: u32 = 4096;
+
+/// Size of a page, a single program command must not cross its end
+const FLASH_PAGE_SIZE: u32 = 256;
+
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_POWER_DOWN: u8 = 0xB9;
+const CMD_RELEASE_POWER_DOWN: u8 = 0xAB;
+
+/// Write in progress bit of the status register
+const STATUS_BUSY: u8 = 0x01;
+
+/// Cycles to wait after releasing the power-down (> 35µs at 16 MHz)
+const RELEASE_POWER_DOWN_CYCLES: u32 = 1000;
+
+/// SPI flash of the dev kit, which also holds the firmware image
+///
+/// The SDK's SPI flash driver is not built, so the SPI mode 0 protocol is bit-banged on the
+/// flash's pins. The flash is kept in power-down between operations.
+///
+/// The dev kit wires the flash's data input to P0_0, which is the hardware reset (active high)
+/// of the chip by default. The reset is only disabled while an operation runs, so it keeps
+/// working otherwise.
+pub(super) struct Flash {
+    cs: Pin<Output>,
+    clk: Pin<Output>,
+    mosi: Pin<Output>,
+    miso: Pin<Input<Floating>>,
+}
+
+impl Flash {
+    pub(super) fn new(
+        cs: Pin<Output>,
+        clk: Pin<Output>,
+        mosi: Pin<Output>,
+        miso: Pin<Input<Floating>>,
+    ) -> Self {
+        Self {
+            cs,
+            clk,
+            mosi,
+            miso,
+        }
+    }
+
+    /// Read `buffer.len()` bytes from `address` on
+    pub(super) fn read(&mut self, address: u32, buffer: &mut [u8]) {
+        self.begin();
+        self.command(&address_command(CMD_READ, address), &[], buffer);
+        self.end();
+    }
+
+    /// Set all bytes of the sector at `address` to `0xFF`
+    pub(super) fn erase_sector(&mut self, address: u32) {
+        self.begin();
+        self.write_enable();
+        self.command(&address_command(CMD_SECTOR_ERASE, address), &[], &mut []);
+        self.wait_ready();
+        self.end();
+    }
+
+    /// Program `data` to erased bytes from `address` on, split at the page ends
+    pub(super) fn program(&mut self, mut address: u32, mut data: &[u8]) {
+        self.begin();
+
+        while !data.is_empty() {
+            let page_left = (FLASH_PAGE_SIZE - address % FLASH_PAGE_SIZE) as usize;
+            let (chunk, rest) = data.split_at(page_left.min(data.len()));
+
+            self.write_enable();
+            self.command(&address_command(CMD_PAGE_PROGRAM, address), chunk, &mut []);
+            self.wait_ready();
+
+            address += chunk.len() as u32;
+            data = rest;
+        }
+
+        self.end();
+    }
+
+    /// Take P0_0 from the reset and wake up the flash
+    fn begin(&mut self) {
+        set_hw_reset(false);
+        self.release_power_down();
+    }
+
+    /// Put the flash in power-down and hand P0_0 back to the reset
+    fn end(&mut self) {
+        self.power_down();
+        // The last bit sent may have been a 1, which would reset the chip
+        let _ = self.mosi.set_low();
+        set_hw_reset(true);
+    }
+
+    fn write_enable(&mut self) {
+        self.command(&[CMD_WRITE_ENABLE], &[], &mut []);
+    }
+
+    fn wait_ready(&mut self) {
+        let mut status = [STATUS_BUSY];
+        while status[0] & STATUS_BUSY != 0 {
+            self.command(&[CMD_READ_STATUS], &[], &mut status);
+        }
+    }
+
+    fn release_power_down(&mut self) {
+        self.command(&[CMD_RELEASE_POWER_DOWN], &[], &mut []);
+        delay(RELEASE_POWER_DOWN_CYCLES);
+    }
+
+    fn power_down(&mut self) {
+        self.command(&[CMD_POWER_DOWN], &[], &mut []);
+    }
+
+    /// Send `header` and `data`, then read `response` within one chip select
+    fn command(&mut self, header: &[u8], data: &[u8], response: &mut [u8]) {
+        let _ = self.cs.set_low();
+
+        for byte in header.iter().chain(data) {
+            self.transfer(*byte);
+        }
+        for byte in response {
+            *byte = self.transfer(0);
+        }
+
+        let _ = self.cs.set_high();
+    }
+
+    /// Shift out `byte` and shift in the answer, MSB first
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut received = 0;
+
+        for bit in (0..8).rev() {
+            let _ = self.mosi.set_state(PinState::from(byte & (1 << bit) != 0));
+            let _ = self.clk.set_high();
+            if self.miso.is_high().unwrap_or(false) {
+                received |= 1 << bit;
+            }
+            let _ = self.clk.set_low();
+        }
+
+        received
+    }
+}
+
+/// Enable or disable the hardware reset on P0_0
+fn set_hw_reset(enabled: bool) {
+    unsafe {
+        (*CRG_AON::ptr())
+            .hwr_ctrl_reg
+            .write(|w| w.disable_hwr().bit(!enabled))
+    };
+}
+
+/// Command byte followed by a 24 bit address
+fn address_command(command: u8, address: u32) -> [u8; 4] {
+    let address = address.to_be_bytes();
+    [command, address[1], address[2], address[3]]
+}
@@ -0,0 +1,77 @@
+use core::{cell::Cell, time::Duration};
+
+use alloc::{boxed::Box, rc::Rc};
+
+use crate::{
+    app::{
+        event::AppEvent,
+        led::{LedPattern, LedPlayer},
+        PeripheralsDriver, TimerDriver,
+    },
+    app_impl::push_event,
+    timer::Da14531Timer,
+};
+
+use super::Da14531Peripherals;
+
+/// Plays LED patterns, every step is timed by its own timer
+pub(super) struct Led {
+    player: LedPlayer,
+    /// Timer of the current step with a flag set once it expired
+    timer: Option<(Da14531Timer, Rc<Cell<bool>>)>,
+}
+
+impl Led {
+    pub(super) fn new() -> Self {
+        Self {
+            player: LedPlayer::new(),
+            timer: None,
+        }
+    }
+}
+
+impl Da14531Peripherals {
+    pub(super) fn led_play_pattern(&mut self, pattern: LedPattern) {
+        self.led_cancel_timer();
+
+        let step = self.led.player.start(pattern);
+        self.led_apply_step(step);
+    }
+
+    pub(super) fn led_on_timer(&mut self) {
+        // Ignore events of a timer, which was replaced after it expired
+        match &self.led.timer {
+            Some((_, expired)) if expired.get() => self.led.timer = None,
+            _ => return,
+        }
+
+        let step = self.led.player.advance();
+        self.led_apply_step(step);
+    }
+
+    fn led_apply_step(&mut self, (state, delay): (bool, Option<Duration>)) {
+        self.set_led(state);
+
+        if let Some(delay) = delay {
+            let expired = Rc::new(Cell::new(false));
+            let timer_expired = expired.clone();
+            self.led.timer = Da14531Timer::create(
+                delay,
+                Box::new(move || {
+                    timer_expired.set(true);
+                    push_event(AppEvent::LedTimer);
+                }),
+            )
+            .map(|timer| (timer, expired));
+        }
+    }
+
+    fn led_cancel_timer(&mut self) {
+        if let Some((timer, expired)) = self.led.timer.take() {
+            // The timer already expired, so it must not be cancelled
+            if !expired.get() {
+                timer.cancel();
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use core::{mem::size_of, ptr, slice};
+
+use crate::app::{
+    auth::{DeviceKey, AUTH_BLOCK_LEN},
+    persistent::PersistentSettings,
+    unlock::{UnlockCredential, UNLOCK_CREDENTIAL_LEN},
+};
+
+use super::{flash::FLASH_SECTOR_SIZE, Da14531Peripherals};
+
+/// Marks the sector as written by `persistent_store` (erased flash reads `0xFF`)
+const PERSISTENT_MAGIC: u32 = 0x5045_5253;
+
+/// Last sector of the first 128 KiB, far behind the firmware image (at most the 48 KiB of RAM)
+const PERSISTENT_ADDRESS: u32 = 0x20000 - FLASH_SECTOR_SIZE;
+
+/// The magic is programmed after the settings, so an interrupted write is not read back
+const SETTINGS_OFFSET: u32 = 4;
+
+/// Marks the key as provisioned
+const DEVICE_KEY_MAGIC: u32 = 0x4445_564B;
+
+/// Sector in front of the settings, it is written in production and never by the firmware
+///
+/// Holds `DEVICE_KEY_MAGIC` (u32 little endian) followed by the 16 bytes of the key.
+const DEVICE_KEY_ADDRESS: u32 = PERSISTENT_ADDRESS - FLASH_SECTOR_SIZE;
+
+/// Marks the unlock credential as provisioned
+const UNLOCK_CREDENTIAL_MAGIC: u32 = 0x554E_4C4B;
+
+/// Second page of the device key sector, written in production like the key
+///
+/// Holds `UNLOCK_CREDENTIAL_MAGIC` (u32 little endian) followed by the 16 bytes of the
+/// credential.
+const UNLOCK_CREDENTIAL_ADDRESS: u32 = DEVICE_KEY_ADDRESS + 0x100;
+
+impl Da14531Peripherals {
+    pub(super) fn persistent_store(&mut self, settings: &PersistentSettings) {
+        let settings = unsafe {
+            slice::from_raw_parts(
+                (settings as *const PersistentSettings).cast::<u8>(),
+                size_of::<PersistentSettings>(),
+            )
+        };
+
+        self.flash.erase_sector(PERSISTENT_ADDRESS);
+        self.flash
+            .program(PERSISTENT_ADDRESS + SETTINGS_OFFSET, settings);
+        self.flash
+            .program(PERSISTENT_ADDRESS, &PERSISTENT_MAGIC.to_le_bytes());
+    }
+
+    pub(super) fn persistent_load(&mut self) -> Option<PersistentSettings> {
+        let mut magic = [0; 4];
+        self.flash.read(PERSISTENT_ADDRESS, &mut magic);
+        if u32::from_le_bytes(magic) != PERSISTENT_MAGIC {
+            return None;
+        }
+
+        let mut settings = [0; size_of::<PersistentSettings>()];
+        self.flash
+            .read(PERSISTENT_ADDRESS + SETTINGS_OFFSET, &mut settings);
+
+        // Any bit pattern is a `PersistentSettings`, it only contains integers
+        Some(unsafe { ptr::read_unaligned(settings.as_ptr().cast::<PersistentSettings>()) })
+    }
+
+    pub(super) fn device_key_load(&mut self) -> Option<DeviceKey> {
+        let mut magic = [0; 4];
+        self.flash.read(DEVICE_KEY_ADDRESS, &mut magic);
+        if u32::from_le_bytes(magic) != DEVICE_KEY_MAGIC {
+            return None;
+        }
+
+        let mut key = [0; AUTH_BLOCK_LEN];
+        self.flash.read(DEVICE_KEY_ADDRESS + 4, &mut key);
+
+        Some(DeviceKey::new(key))
+    }
+
+    pub(super) fn unlock_credential_load(&mut self) -> Option<UnlockCredential> {
+        let mut magic = [0; 4];
+        self.flash.read(UNLOCK_CREDENTIAL_ADDRESS, &mut magic);
+        if u32::from_le_bytes(magic) != UNLOCK_CREDENTIAL_MAGIC {
+            return None;
+        }
+
+        let mut credential = [0; UNLOCK_CREDENTIAL_LEN];
+        self.flash
+            .read(UNLOCK_CREDENTIAL_ADDRESS + 4, &mut credential);
+
+        Some(UnlockCredential::new(credential))
+    }
+}
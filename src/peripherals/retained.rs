@@ -0,0 +1,43 @@
+use core::{mem::MaybeUninit, ptr};
+
+use crate::app::retained::RetainedState;
+
+use super::Da14531Peripherals;
+
+/// Marks `RETAINED` as written by `retained_store` (RAM content is random after power up)
+const RETAINED_MAGIC: u32 = 0x5245_5441;
+
+#[repr(C)]
+struct RetainedSlot {
+    magic: u32,
+    state: RetainedState,
+}
+
+/// Not touched by the startup code, so it keeps its content during hibernation (RAM3 is kept powered)
+#[link_section = "retention_mem_area_uninit"]
+static mut RETAINED: MaybeUninit<RetainedSlot> = MaybeUninit::uninit();
+
+impl Da14531Peripherals {
+    pub(super) fn retained_store(&mut self, state: &RetainedState) {
+        unsafe {
+            ptr::write_volatile(
+                RETAINED.as_mut_ptr(),
+                RetainedSlot {
+                    magic: RETAINED_MAGIC,
+                    state: *state,
+                },
+            );
+        }
+    }
+
+    pub(super) fn retained_load(&mut self) -> Option<RetainedState> {
+        let slot = unsafe { RETAINED.as_ptr() };
+
+        let magic = unsafe { ptr::read_volatile(ptr::addr_of!((*slot).magic)) };
+        if magic != RETAINED_MAGIC {
+            return None;
+        }
+
+        Some(unsafe { ptr::read_volatile(ptr::addr_of!((*slot).state)) })
+    }
+}
@@ -0,0 +1,1031 @@
+use core::time::Duration;
+use std::{cell::RefCell, vec::Vec};
+
+use alloc::boxed::Box;
+
+use crate::app::{
+    adc::{AdcChannel, AdcSettings},
+    auth::{AuthBlock, DeviceKey},
+    battery::BatteryType,
+    conn_params::PreferredConnectionParams,
+    connection::Notification,
+    event::AppEvent,
+    filter::{AdcFilters, Filter},
+    led::{LedPattern, LedPlayer},
+    persistent::PersistentSettings,
+    retained::RetainedState,
+    sensor::is_sensor_channel,
+    temperature::{check_temperature, TemperatureError},
+    unlock::{UnlockCredential, UNLOCK_CREDENTIAL_LEN},
+    App, BleDriver, PeripheralsDriver, Sound, TimerDriver,
+};
+
+/// Defines the `SimApp` for convenience
+pub type SimApp = App<SimPeripherals, SimBle, SimTimer>;
+
+/// Recorded call to the `BleDriver`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BleCall {
+    StartAdvertising(Duration),
+    StopAdvertising,
+    Disconnect(u8),
+    RequestConnectionParams(u8, PreferredConnectionParams),
+    Notify(u8, Notification, Vec<u8>),
+}
+
+/// Timer which is registered by `SimTimer::create` and waits for its deadline
+struct PendingTimer {
+    id: usize,
+    deadline: Duration,
+    callback: Box<dyn Fn()>,
+}
+
+#[derive(Default)]
+struct SimState {
+    ble_calls: Vec<BleCall>,
+    events: Vec<AppEvent>,
+    timers: Vec<PendingTimer>,
+    next_timer_id: usize,
+    now: Duration,
+    /// Survives dropping the app, like retained RAM survives hibernation
+    retained: Option<RetainedState>,
+    /// Survives dropping the app, like flash survives power loss
+    persistent: Option<PersistentSettings>,
+    /// Timer, which ends the running advertising period
+    advertising: Option<SimTimer>,
+    /// Connection indices of the connected centrals
+    connected: Vec<u8>,
+    /// The production data (device key, unlock credential) is missing
+    unprovisioned: bool,
+}
+
+std::thread_local! {
+    /// State of the static drivers, one per test thread
+    static STATE: RefCell<SimState> = RefCell::new(SimState::default());
+}
+
+/// In-memory peripherals, which record every call made by the app
+pub struct SimPeripherals {
+    /// Current state of the LED
+    pub led: bool,
+    /// Every state passed to `set_led`
+    pub led_history: Vec<bool>,
+    /// Every pattern passed to `play_led_pattern`
+    pub led_patterns: Vec<LedPattern>,
+    /// Steps through the current pattern, `on_led_timer` advances it
+    pub led_player: LedPlayer,
+    /// Delay until the next step of the current pattern
+    pub led_delay: Option<Duration>,
+    /// Every sound passed to `play_sound` with its `repeat` flag
+    pub sounds: Vec<(Sound, bool)>,
+    /// Number of `start_hibernation` calls
+    pub hibernations: usize,
+    /// Number of `feed_watchdog` calls
+    pub watchdog_feeds: usize,
+    /// Number of `on_pwm_interrupt` calls
+    pub pwm_interrupts: usize,
+    /// Die temperature returned by `get_temperature` in milli °C
+    pub temperature: i32,
+    /// Battery voltage returned by `get_battery_voltage` in mV
+    pub battery_voltage: u16,
+    pub battery_type: BatteryType,
+    /// Core supply voltage returned by `get_vddd_voltage` in mV
+    pub vddd_voltage: u16,
+    /// 16 bit samples returned by `sample_adc`, indexed by `AdcChannel`
+    pub adc_samples: [u16; AdcChannel::COUNT],
+    /// Filters set by `set_adc_filter`, applied by `sample_adc`
+    pub adc_filters: RefCell<AdcFilters>,
+    /// Next byte returned by `random_bytes` (counts up)
+    pub next_random: u8,
+}
+
+/// Key returned by `SimPeripherals::load_device_key`
+pub const SIM_DEVICE_KEY: AuthBlock = *b"SIM-DEVICEKEY-00";
+
+/// Credential returned by `SimPeripherals::load_unlock_credential`
+pub const SIM_UNLOCK_CREDENTIAL: [u8; UNLOCK_CREDENTIAL_LEN] = *b"SIM-UNLOCKCRED00";
+
+impl PeripheralsDriver for SimPeripherals {
+    fn new() -> Self {
+        Self {
+            led: false,
+            led_history: Vec::new(),
+            led_patterns: Vec::new(),
+            led_player: LedPlayer::new(),
+            led_delay: None,
+            sounds: Vec::new(),
+            hibernations: 0,
+            watchdog_feeds: 0,
+            pwm_interrupts: 0,
+            temperature: 25000,
+            battery_voltage: 1500,
+            battery_type: BatteryType::Aaa,
+            vddd_voltage: 900,
+            adc_samples: [0; AdcChannel::COUNT],
+            adc_filters: RefCell::new(AdcFilters::default()),
+            next_random: 0,
+        }
+    }
+
+    /// Record the sound and finish it right away
+    fn play_sound(
+        &mut self,
+        sound: Sound,
+        repeat: bool,
+        finish_callback: Option<Box<dyn FnOnce()>>,
+    ) {
+        self.sounds.push((sound, repeat));
+
+        if let Some(finish_callback) = finish_callback {
+            finish_callback();
+        }
+    }
+
+    fn start_hibernation(&mut self) {
+        self.hibernations += 1;
+    }
+
+    /// Like on the board, only the pins used by `SENSORS` are analog inputs
+    fn sample_adc(&self, settings: &AdcSettings) -> Option<u16> {
+        if settings.channel.is_external() && !is_sensor_channel(settings.channel) {
+            return None;
+        }
+
+        let sample = self.adc_samples[settings.channel as usize];
+        Some(
+            self.adc_filters
+                .borrow_mut()
+                .apply(settings.channel, sample),
+        )
+    }
+
+    fn set_adc_filter(&mut self, channel: AdcChannel, filter: Option<Box<dyn Filter>>) {
+        self.adc_filters.get_mut().set(channel, filter);
+    }
+
+    /// Return `temperature` instead of converting a sample, so it is exact
+    fn get_temperature(&self) -> Result<i32, TemperatureError> {
+        check_temperature(self.temperature)
+    }
+
+    fn get_battery_voltage(&self) -> u16 {
+        self.battery_voltage
+    }
+
+    fn battery_type(&self) -> BatteryType {
+        self.battery_type
+    }
+
+    fn get_vddd_voltage(&self) -> u16 {
+        self.vddd_voltage
+    }
+
+    fn feed_watchdog(&mut self) {
+        self.watchdog_feeds += 1;
+    }
+
+    fn set_led(&mut self, state: bool) {
+        self.led = state;
+        self.led_history.push(state);
+    }
+
+    /// Record the pattern and show its first step, the steps are advanced by `on_led_timer`
+    fn play_led_pattern(&mut self, pattern: LedPattern) {
+        self.led_patterns.push(pattern);
+
+        let (state, delay) = self.led_player.start(pattern);
+        self.set_led(state);
+        self.led_delay = delay;
+    }
+
+    fn on_led_timer(&mut self) {
+        let (state, delay) = self.led_player.advance();
+        self.set_led(state);
+        self.led_delay = delay;
+    }
+
+    fn on_pwm_interrupt(&mut self) {
+        self.pwm_interrupts += 1;
+    }
+
+    fn store_retained(&mut self, state: &RetainedState) {
+        STATE.with(|sim_state| sim_state.borrow_mut().retained = Some(*state));
+    }
+
+    fn load_retained(&mut self) -> Option<RetainedState> {
+        STATE.with(|state| state.borrow().retained)
+    }
+
+    fn store_persistent(&mut self, settings: &PersistentSettings) {
+        STATE.with(|state| state.borrow_mut().persistent = Some(*settings));
+    }
+
+    fn load_persistent(&mut self) -> Option<PersistentSettings> {
+        STATE.with(|state| state.borrow().persistent)
+    }
+
+    fn load_device_key(&mut self) -> Option<DeviceKey> {
+        (!SimPeripherals::is_unprovisioned()).then(|| DeviceKey::new(SIM_DEVICE_KEY))
+    }
+
+    fn load_unlock_credential(&mut self) -> Option<UnlockCredential> {
+        (!SimPeripherals::is_unprovisioned()).then(|| UnlockCredential::new(SIM_UNLOCK_CREDENTIAL))
+    }
+
+    /// Predictable counter instead of random numbers
+    fn random_bytes(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = self.next_random;
+            self.next_random = self.next_random.wrapping_add(1);
+        }
+    }
+
+    /// Not AES, see `SimPeripherals::sim_encrypt`
+    fn aes128_encrypt(&mut self, key: &AuthBlock, block: &AuthBlock) -> Option<AuthBlock> {
+        Some(Self::sim_encrypt(key, block))
+    }
+}
+
+impl SimPeripherals {
+    /// Leave out the production data from the next `init_peripherals` on
+    pub fn erase_provisioning() {
+        STATE.with(|state| state.borrow_mut().unprovisioned = true);
+    }
+
+    fn is_unprovisioned() -> bool {
+        STATE.with(|state| state.borrow().unprovisioned)
+    }
+
+    /// Stand-in for AES-128 (key XOR block), so tests can compute the expected response
+    pub fn sim_encrypt(key: &AuthBlock, block: &AuthBlock) -> AuthBlock {
+        let mut result = *block;
+        for (byte, key) in result.iter_mut().zip(key) {
+            *byte ^= key;
+        }
+        result
+    }
+}
+
+/// In-memory BLE stack, which records every call made by the app
+///
+/// Advertising, connections and disconnections raise the same events as the SDK does.
+pub struct SimBle;
+
+impl SimBle {
+    /// Get all calls recorded on the current thread
+    pub fn calls() -> Vec<BleCall> {
+        STATE.with(|state| state.borrow().ble_calls.clone())
+    }
+
+    /// Forget all calls recorded on the current thread
+    pub fn clear_calls() {
+        STATE.with(|state| state.borrow_mut().ble_calls.clear());
+    }
+
+    /// Check if an advertising period is running
+    pub fn is_advertising() -> bool {
+        STATE.with(|state| state.borrow().advertising.is_some())
+    }
+
+    /// A central connects, this ends advertising without `AdvertisingStopped` (like the SDK)
+    pub fn connect_central(conidx: u8) {
+        Self::end_advertising();
+        STATE.with(|state| state.borrow_mut().connected.push(conidx));
+        SimEvents::sink(AppEvent::Connect(Some(conidx)));
+    }
+
+    /// The central `conidx` disconnects, the SDK asks to advertise before it reports this
+    pub fn disconnect_central(conidx: u8) {
+        let connected = STATE.with(|state| {
+            let connected = &mut state.borrow_mut().connected;
+            let count = connected.len();
+            connected.retain(|connected| *connected != conidx);
+            connected.len() != count
+        });
+
+        if connected {
+            SimEvents::sink(AppEvent::StartAdvertising);
+            SimEvents::sink(AppEvent::Disconnect(conidx));
+        }
+    }
+
+    fn record(call: BleCall) {
+        STATE.with(|state| state.borrow_mut().ble_calls.push(call));
+    }
+
+    /// Stop the running advertising period, returns `false` if there was none
+    fn end_advertising() -> bool {
+        match STATE.with(|state| state.borrow_mut().advertising.take()) {
+            Some(timer) => {
+                timer.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl BleDriver for SimBle {
+    /// Panics if advertising is already running, the stack would reject this
+    fn start_adverstising(period: Duration) {
+        Self::record(BleCall::StartAdvertising(period));
+
+        assert!(!Self::is_advertising(), "Advertising is already running");
+
+        let timer = SimTimer::create(
+            period,
+            Box::new(|| {
+                STATE.with(|state| state.borrow_mut().advertising = None);
+                SimEvents::sink(AppEvent::AdvertisingStopped);
+            }),
+        );
+        STATE.with(|state| state.borrow_mut().advertising = timer);
+    }
+
+    fn stop_adverstising() {
+        Self::record(BleCall::StopAdvertising);
+
+        if Self::end_advertising() {
+            SimEvents::sink(AppEvent::AdvertisingStopped);
+        }
+    }
+
+    fn disconnect(conidx: u8) {
+        Self::record(BleCall::Disconnect(conidx));
+        Self::disconnect_central(conidx);
+    }
+
+    fn request_connection_params(conidx: u8, params: &PreferredConnectionParams) {
+        Self::record(BleCall::RequestConnectionParams(conidx, *params));
+    }
+
+    fn notify(conidx: u8, notification: Notification, value: &[u8]) {
+        Self::record(BleCall::Notify(conidx, notification, value.to_vec()));
+    }
+}
+
+/// Event queue of the current thread, which stands in for the firmware's event queue
+pub struct SimEvents;
+
+impl SimEvents {
+    /// Queue an event, pass this to `App::set_event_sink`
+    pub fn sink(event: AppEvent) {
+        STATE.with(|state| state.borrow_mut().events.push(event));
+    }
+
+    /// Hand all queued events to the app, including events queued meanwhile
+    pub fn dispatch(app: &mut SimApp) {
+        while let Some(event) = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            (!state.events.is_empty()).then(|| state.events.remove(0))
+        }) {
+            app.handle_event(event);
+        }
+    }
+}
+
+/// Virtual clock, which only moves when advanced by the test
+pub struct SimClock;
+
+impl SimClock {
+    /// Get the time since the start of the current thread's clock
+    pub fn now() -> Duration {
+        STATE.with(|state| state.borrow().now)
+    }
+
+    /// Move the clock forward and fire every timer which expires on the way, in order
+    pub fn advance(duration: Duration) {
+        let target = Self::now() + duration;
+
+        loop {
+            let next = STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let (idx, _) = state
+                    .timers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, timer)| timer.deadline <= target)
+                    .min_by_key(|(_, timer)| (timer.deadline, timer.id))?;
+                let timer = state.timers.remove(idx);
+                state.now = timer.deadline;
+                Some(timer)
+            });
+
+            // The callback runs without holding the state, so it may create timers itself
+            match next {
+                Some(timer) => (timer.callback)(),
+                None => break,
+            }
+        }
+
+        STATE.with(|state| state.borrow_mut().now = target);
+    }
+
+    /// Get the remaining time of all pending timers on the current thread
+    pub fn pending() -> Vec<Duration> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state
+                .timers
+                .iter()
+                .map(|timer| timer.deadline - state.now)
+                .collect()
+        })
+    }
+}
+
+/// Timer driven by `SimClock`
+pub struct SimTimer(usize);
+
+impl TimerDriver for SimTimer {
+    fn create(delay: Duration, callback: Box<dyn Fn()>) -> Option<Self> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let id = state.next_timer_id;
+            state.next_timer_id += 1;
+            let deadline = state.now + delay;
+            state.timers.push(PendingTimer {
+                id,
+                deadline,
+                callback,
+            });
+            Some(Self(id))
+        })
+    }
+
+    /// Panics if the timer already expired, its handle may have been reused on the target
+    fn cancel(self) {
+        STATE.with(|state| {
+            let timers = &mut state.borrow_mut().timers;
+            let count = timers.len();
+            timers.retain(|timer| timer.id != self.0);
+            assert!(timers.len() != count, "Cancelled an expired timer");
+        });
+    }
+
+    fn reschedule(self, delay: Duration) -> Option<Self> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let deadline = state.now + delay;
+            let timer = state.timers.iter_mut().find(|timer| timer.id == self.0)?;
+            timer.deadline = deadline;
+            Some(self)
+        })
+    }
+
+    fn now() -> Duration {
+        SimClock::now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{
+        config::{
+            AdvertisingConfig, CalibrationCommand, TemperatureCalibration, TemperatureConfig,
+            TemperatureThresholds,
+        },
+        conn_params::{ConnectionParams, CONN_PARAM_REQUEST_DELAY},
+        history::HISTORY_CHUNK_LEN,
+        led::LedEventPatterns,
+        state::AppState,
+        temperature::TemperatureLevel,
+        unlock::UnlockStatus,
+    };
+
+    const CREDENTIAL: [u8; UNLOCK_CREDENTIAL_LEN] = SIM_UNLOCK_CREDENTIAL;
+
+    const ADV_TIMEOUT: Duration = AdvertisingConfig::DEFAULT.timeout();
+
+    /// App like after `periph_init`, advertising after the SDK's first `StartAdvertising`
+    fn advertising_app() -> SimApp {
+        let mut app = SimApp::new();
+        app.set_event_sink(SimEvents::sink);
+        app.init_peripherals();
+
+        SimEvents::sink(AppEvent::StartAdvertising);
+        SimEvents::dispatch(&mut app);
+        app
+    }
+
+    /// Move the clock forward and hand the events raised on the way to the app
+    fn advance(app: &mut SimApp, duration: Duration) {
+        SimClock::advance(duration);
+        SimEvents::dispatch(app);
+    }
+
+    fn connect(app: &mut SimApp, conidx: u8) {
+        SimBle::connect_central(conidx);
+        SimEvents::dispatch(app);
+    }
+
+    fn disconnect(app: &mut SimApp, conidx: u8) {
+        SimBle::disconnect_central(conidx);
+        SimEvents::dispatch(app);
+    }
+
+    #[test]
+    fn advertises_until_timeout_then_hibernates() {
+        let mut app = advertising_app();
+        assert_eq!(app.state(), AppState::Advertising);
+
+        // Every period ends with `AdvertisingStopped` and starts the next one
+        advance(&mut app, ADV_TIMEOUT - Duration::from_secs(1));
+        assert_eq!(app.state(), AppState::Advertising);
+        assert!(SimBle::is_advertising());
+
+        advance(&mut app, Duration::from_secs(1));
+        assert_eq!(app.state(), AppState::Hibernating);
+        assert_eq!(app.peripherals().hibernations, 1);
+        assert!(!SimBle::is_advertising());
+    }
+
+    #[test]
+    fn connection_cancels_hibernation() {
+        let mut app = advertising_app();
+
+        connect(&mut app, 0);
+        assert_eq!(app.state(), AppState::Connected);
+        assert_eq!(app.peripherals().sounds, [(Sound::Connected, false)]);
+
+        advance(&mut app, ADV_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(app.state(), AppState::Connected);
+        assert_eq!(app.peripherals().hibernations, 0);
+    }
+
+    #[test]
+    fn last_disconnect_hibernates_after_timeout() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        disconnect(&mut app, 0);
+        assert_eq!(app.state(), AppState::Advertising);
+        assert_eq!(
+            app.peripherals().sounds,
+            [(Sound::Connected, false), (Sound::Disconnected, false)]
+        );
+        assert!(SimBle::is_advertising());
+
+        advance(&mut app, ADV_TIMEOUT);
+        assert_eq!(app.state(), AppState::Hibernating);
+        assert_eq!(app.peripherals().hibernations, 1);
+    }
+
+    #[test]
+    fn two_centrals_connect_in_a_row() {
+        let mut app = advertising_app();
+
+        connect(&mut app, 0);
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 1);
+        assert_eq!(app.state(), AppState::Connected);
+        assert_eq!(app.connections().len(), 2);
+        assert!(SimBle::is_advertising());
+
+        // Advertising continues with every period, until all slots are taken
+        advance(&mut app, AdvertisingConfig::DEFAULT.period());
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 2);
+        assert!(!SimBle::is_advertising());
+
+        disconnect(&mut app, 1);
+        assert_eq!(app.state(), AppState::Connected);
+        assert!(SimBle::is_advertising());
+    }
+
+    #[test]
+    fn advertising_continues_after_the_last_disconnect() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+        disconnect(&mut app, 0);
+
+        advance(&mut app, ADV_TIMEOUT - Duration::from_secs(1));
+        assert_eq!(app.state(), AppState::Advertising);
+        assert!(SimBle::is_advertising());
+
+        advance(&mut app, Duration::from_secs(1));
+        assert_eq!(app.state(), AppState::Hibernating);
+    }
+
+    #[test]
+    fn recording_history_keeps_the_device_awake() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        app.on_set_temperature_config(TemperatureConfig::new(10, 500, 60).unwrap());
+        SimEvents::sink(AppEvent::TemperatureMeasure);
+        disconnect(&mut app, 0);
+
+        advance(&mut app, ADV_TIMEOUT * 3);
+        assert_eq!(app.state(), AppState::Advertising);
+        assert_eq!(app.peripherals().hibernations, 0);
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 1);
+        let (chunk, len) = app.read_history(1);
+        assert_eq!(len, HISTORY_CHUNK_LEN);
+        // The oldest sample was taken right after the history was enabled
+        assert_eq!(chunk[4..8], (ADV_TIMEOUT * 3).as_secs().to_be_bytes()[4..]);
+
+        // Without the history the device hibernates again
+        app.on_set_temperature_config(TemperatureConfig::new(10, 500, 0).unwrap());
+        disconnect(&mut app, 1);
+        advance(&mut app, ADV_TIMEOUT);
+        assert_eq!(app.state(), AppState::Hibernating);
+        assert_eq!(app.peripherals().hibernations, 1);
+    }
+
+    #[test]
+    fn idle_connection_is_disconnected() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        let idle_timeout = app.idle_config().timeout().unwrap();
+        advance(&mut app, idle_timeout - Duration::from_secs(1));
+        app.on_activity(0);
+
+        advance(&mut app, idle_timeout - Duration::from_secs(1));
+        assert_eq!(app.connections().len(), 1);
+
+        advance(&mut app, Duration::from_secs(1));
+        assert!(SimBle::calls().contains(&BleCall::Disconnect(0)));
+        assert!(app.connections().is_empty());
+    }
+
+    #[test]
+    fn unaccepted_conn_params_are_requested_again() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        let params = ConnectionParams {
+            interval: 24,
+            latency: 0,
+            supervision_timeout: 400,
+        };
+        app.handle_event(AppEvent::ConnParamsUpdated(0, params));
+        SimBle::clear_calls();
+
+        advance(&mut app, CONN_PARAM_REQUEST_DELAY);
+        assert_eq!(
+            SimBle::calls(),
+            [BleCall::RequestConnectionParams(
+                0,
+                app.preferred_connection_params()
+            )]
+        );
+        assert_eq!(app.connection_params(0), Some(params));
+    }
+
+    #[test]
+    fn rejection_only_retries_the_sent_request() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+        connect(&mut app, 1);
+
+        let params = ConnectionParams {
+            interval: 24,
+            latency: 0,
+            supervision_timeout: 400,
+        };
+        app.handle_event(AppEvent::ConnParamsUpdated(0, params));
+        advance(&mut app, CONN_PARAM_REQUEST_DELAY);
+
+        // The request of connection 1 is scheduled, but not sent when the rejection arrives
+        app.handle_event(AppEvent::ConnParamsUpdated(1, params));
+        app.handle_event(AppEvent::ConnParamsRejected);
+
+        let requests = |app: &SimApp, conidx| {
+            let connection = app.connections().get(conidx).unwrap();
+            connection.conn_params.requests()
+        };
+        assert_eq!(requests(&app, 0), 2);
+        assert_eq!(requests(&app, 1), 1);
+
+        SimBle::clear_calls();
+        advance(&mut app, Duration::from_secs(2));
+        let preferred = app.preferred_connection_params();
+        assert_eq!(
+            SimBle::calls(),
+            [
+                BleCall::RequestConnectionParams(1, preferred),
+                BleCall::RequestConnectionParams(0, preferred)
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_unlock_locks_out_until_the_timer_expires() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        assert_eq!(app.on_unlock_attempt(0, &[0; 16]), UnlockStatus::Failed);
+        assert_eq!(
+            app.on_unlock_attempt(0, &CREDENTIAL),
+            UnlockStatus::LockedOut
+        );
+
+        let lockout = app.failed_unlock_attempts().lockout();
+        advance(&mut app, lockout);
+        assert_eq!(
+            app.on_unlock_attempt(0, &CREDENTIAL),
+            UnlockStatus::Unlocked
+        );
+        assert!(!app.is_unlock_locked_out());
+    }
+
+    #[test]
+    fn unlock_stops_the_alarm() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        app.handle_event(AppEvent::Alarm);
+        assert_eq!(app.state(), AppState::Alarm);
+        assert_eq!(app.peripherals().sounds.last(), Some(&(Sound::Alarm, true)));
+
+        assert_eq!(
+            app.on_unlock_attempt(0, &CREDENTIAL),
+            UnlockStatus::Unlocked
+        );
+        assert_eq!(app.state(), AppState::Connected);
+        assert!(!app.is_alarm_on());
+    }
+
+    #[test]
+    fn connecting_does_not_end_the_alarm() {
+        let mut app = advertising_app();
+        app.handle_event(AppEvent::Alarm);
+
+        connect(&mut app, 0);
+        assert_eq!(app.state(), AppState::Alarm);
+        assert_eq!(app.connections().len(), 1);
+        assert_eq!(app.peripherals().sounds, [(Sound::Alarm, true)]);
+
+        assert_eq!(
+            app.on_unlock_attempt(0, &CREDENTIAL),
+            UnlockStatus::Unlocked
+        );
+        assert_eq!(app.state(), AppState::Connected);
+    }
+
+    #[test]
+    fn unprovisioned_device_rejects_every_unlock() {
+        SimPeripherals::erase_provisioning();
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        assert_eq!(app.on_unlock_attempt(0, &CREDENTIAL), UnlockStatus::Failed);
+        assert_eq!(
+            app.on_unlock_attempt(0, &[0; UNLOCK_CREDENTIAL_LEN]),
+            UnlockStatus::Failed
+        );
+        // A device without a credential can not be brute-forced, so nothing is counted
+        assert_eq!(app.failed_unlock_attempts().total(), 0);
+        assert!(!app.is_unlock_locked_out());
+    }
+
+    /// Submit a wrong credential once the lockout of the last failure expired
+    fn fail_unlock(app: &mut SimApp, conidx: u8) -> UnlockStatus {
+        let lockout = app.failed_unlock_attempts().lockout();
+        advance(app, lockout);
+        app.on_unlock_attempt(conidx, &[0; UNLOCK_CREDENTIAL_LEN])
+    }
+
+    #[test]
+    fn alarm_keeps_advertising_until_unlocked() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        // The central is disconnected after its third failure
+        for _ in 0..3 {
+            assert_eq!(fail_unlock(&mut app, 0), UnlockStatus::Failed);
+        }
+        SimEvents::dispatch(&mut app);
+        assert!(app.connections().is_empty());
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 1);
+        fail_unlock(&mut app, 1);
+        fail_unlock(&mut app, 1);
+        assert_eq!(app.state(), AppState::Alarm);
+
+        disconnect(&mut app, 1);
+        assert_eq!(app.state(), AppState::Alarm);
+
+        // Neither the hibernation timer nor the end of a period stops advertising
+        advance(&mut app, ADV_TIMEOUT * 2);
+        assert_eq!(app.state(), AppState::Alarm);
+        assert_eq!(app.peripherals().hibernations, 0);
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 2);
+        assert_eq!(app.state(), AppState::Alarm);
+
+        let lockout = app.failed_unlock_attempts().lockout();
+        advance(&mut app, lockout);
+        assert_eq!(
+            app.on_unlock_attempt(2, &CREDENTIAL),
+            UnlockStatus::Unlocked
+        );
+        assert_eq!(app.state(), AppState::Connected);
+    }
+
+    #[test]
+    fn temperature_above_threshold_raises_the_alarm() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        app.on_set_temperature_thresholds(TemperatureThresholds::new(0, 30000, 1000).unwrap());
+        app.peripherals().temperature = 35000;
+        SimEvents::sink(AppEvent::TemperatureMeasure);
+        SimEvents::dispatch(&mut app);
+
+        assert_eq!(app.temperature_level(), TemperatureLevel::High);
+        assert_eq!(app.state(), AppState::Alarm);
+
+        // Sampling continues with the configured interval
+        app.peripherals().temperature = 25000;
+        let interval = app.temperature_config().interval();
+        advance(&mut app, interval);
+        assert_eq!(app.temperature_level(), TemperatureLevel::Normal);
+    }
+
+    #[test]
+    fn alarm_while_advertising() {
+        let mut app = advertising_app();
+
+        app.on_set_temperature_thresholds(TemperatureThresholds::new(0, 30000, 1000).unwrap());
+        app.peripherals().temperature = 35000;
+        SimEvents::sink(AppEvent::TemperatureMeasure);
+        SimEvents::dispatch(&mut app);
+        assert_eq!(app.state(), AppState::Alarm);
+
+        // The alarm neither hibernates nor stops advertising after the timeout
+        advance(&mut app, ADV_TIMEOUT * 2);
+        assert_eq!(app.state(), AppState::Alarm);
+        assert_eq!(app.peripherals().hibernations, 0);
+        assert!(SimBle::is_advertising());
+
+        connect(&mut app, 0);
+        assert_eq!(
+            app.on_unlock_attempt(0, &CREDENTIAL),
+            UnlockStatus::Unlocked
+        );
+        assert_eq!(app.state(), AppState::Connected);
+    }
+
+    #[test]
+    fn written_led_pattern_overrides_the_event_patterns() {
+        let mut app = advertising_app();
+        app.set_led_event_patterns(LedEventPatterns {
+            connect: LedPattern::DoubleBlink,
+            ..LedEventPatterns::DEFAULT
+        });
+
+        connect(&mut app, 0);
+        assert_eq!(
+            app.peripherals().led_patterns.last(),
+            Some(&LedPattern::DoubleBlink)
+        );
+        assert!(app.get_led_state());
+
+        app.on_set_led(false);
+        assert_eq!(
+            app.peripherals().led_patterns.last(),
+            Some(&LedPattern::Off)
+        );
+        assert!(!app.peripherals().led);
+        assert!(!app.get_led_state());
+
+        // The alarm still overrides a written pattern
+        app.handle_event(AppEvent::Alarm);
+        assert_eq!(app.led_pattern(), LedEventPatterns::DEFAULT.alarm);
+        assert!(app.get_led_state());
+    }
+
+    #[test]
+    fn event_patterns_are_shown_by_default() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+        assert_eq!(app.led_pattern(), LedEventPatterns::DEFAULT.connect);
+
+        disconnect(&mut app, 0);
+        assert_eq!(app.led_pattern(), LedEventPatterns::DEFAULT.disconnect);
+        assert_ne!(
+            LedEventPatterns::DEFAULT.connect,
+            LedEventPatterns::DEFAULT.disconnect
+        );
+
+        // Hibernation stops the timer of the disconnect pattern
+        advance(&mut app, ADV_TIMEOUT);
+        assert_eq!(
+            app.peripherals().led_patterns.last(),
+            Some(&LedPattern::Off)
+        );
+    }
+
+    #[test]
+    fn selecting_off_restores_the_event_patterns() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        app.on_set_led_pattern(LedPattern::Sos);
+        disconnect(&mut app, 0);
+        assert_eq!(app.led_pattern(), LedPattern::Sos);
+
+        app.on_set_led_pattern(LedPattern::Off);
+        assert_eq!(
+            app.peripherals().led_patterns.last(),
+            Some(&LedEventPatterns::DEFAULT.disconnect)
+        );
+    }
+
+    #[test]
+    fn calibration_corrects_the_calibrated_readings_and_persists() {
+        let mut app = advertising_app();
+        app.peripherals().temperature = 25000;
+
+        // The device reads 1 °C too low
+        let raise = CalibrationCommand::decode(&calibration_points(20000, 21000, 30000, 31000));
+        app.on_calibrate_temperature(raise.unwrap());
+        assert_eq!(app.get_temperature(), Ok(26000));
+
+        // The second calibration is done with the corrected readings
+        let lower = CalibrationCommand::decode(&calibration_points(26000, 25000, 36000, 35000));
+        app.on_calibrate_temperature(lower.unwrap());
+        assert_eq!(
+            app.temperature_calibration(),
+            TemperatureCalibration::FACTORY
+        );
+
+        app.on_calibrate_temperature(raise.unwrap());
+
+        // The flash is written after the handler returned
+        assert_eq!(app.peripherals().load_persistent(), None);
+        SimEvents::dispatch(&mut app);
+        assert!(app.peripherals().load_persistent().is_some());
+
+        // A power cycle loses RAM, but the calibration is read back
+        let mut app = SimApp::new();
+        app.init_peripherals();
+        app.peripherals().temperature = 25000;
+        assert_eq!(app.get_temperature(), Ok(26000));
+    }
+
+    fn calibration_points(
+        measured_a: i32,
+        actual_a: i32,
+        measured_b: i32,
+        actual_b: i32,
+    ) -> Vec<u8> {
+        let mut value = std::vec![0x01];
+        for field in [measured_a, actual_a, measured_b, actual_b] {
+            value.extend_from_slice(&field.to_be_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn provisioned_key_authenticates() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+
+        let nonce = app.on_auth_nonce_request(0);
+        let response = SimPeripherals::sim_encrypt(&SIM_DEVICE_KEY, &nonce);
+        assert!(app.on_auth_response(0, &response));
+        assert!(app.is_authenticated(0));
+
+        // Every nonce is answered only once
+        assert!(!app.on_auth_response(0, &response));
+    }
+
+    /// Answer a fresh challenge of connection `conidx` with the provisioned key
+    fn authenticate(app: &mut SimApp, conidx: u8) -> bool {
+        let nonce = app.on_auth_nonce_request(conidx);
+        let response = SimPeripherals::sim_encrypt(&SIM_DEVICE_KEY, &nonce);
+        app.on_auth_response(conidx, &response)
+    }
+
+    #[test]
+    fn authentication_is_per_connection() {
+        let mut app = advertising_app();
+        connect(&mut app, 0);
+        connect(&mut app, 1);
+
+        // An idle central, which never authenticates, does not affect the other one
+        assert!(authenticate(&mut app, 0));
+        assert!(app.is_authenticated(0));
+        assert!(!app.is_authenticated(1));
+
+        // A central reconnecting on the same index starts unauthenticated
+        disconnect(&mut app, 0);
+        connect(&mut app, 0);
+        assert!(!app.is_authenticated(0));
+
+        assert!(authenticate(&mut app, 1));
+        assert!(!app.is_authenticated(0));
+        assert!(app.is_authenticated(1));
+    }
+}
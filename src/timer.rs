@@ -0,0 +1,66 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, rc::Rc};
+use da14531_sdk::{
+    app_modules::timer::AppTimer,
+    bindings::{ke_time, KE_TIMER_DELAY_MAX},
+};
+
+use crate::app::TimerDriver;
+
+/// `ke_time` counts 10ms ticks in 23 bits (wraps around after ~23h), twice `KE_TIMER_DELAY_MAX`
+const KE_TIME_MASK: u32 = 0x7F_FFFF;
+
+/// Timer based on the SDK's `AppTimer` (resolution: 10ms)
+pub struct Da14531Timer {
+    timer: AppTimer,
+    /// Kept to be able to re-create the timer in `reschedule`
+    callback: Rc<dyn Fn()>,
+}
+
+impl Da14531Timer {
+    /// Convert `delay` to timer units (10ms), at least one unit
+    fn delay_to_timer_units(delay: Duration) -> u32 {
+        ((delay.as_millis() / 10) as u32).clamp(1, KE_TIMER_DELAY_MAX)
+    }
+
+    fn start(delay: Duration, callback: Rc<dyn Fn()>) -> Option<Self> {
+        let timer_callback = callback.clone();
+        let timer = AppTimer::new(
+            Self::delay_to_timer_units(delay),
+            Box::new(move || timer_callback()),
+        )?;
+
+        Some(Self { timer, callback })
+    }
+}
+
+impl TimerDriver for Da14531Timer {
+    fn create(delay: Duration, callback: Box<dyn Fn()>) -> Option<Self> {
+        Self::start(delay, Rc::from(callback))
+    }
+
+    fn cancel(self) {
+        self.timer.cancel();
+    }
+
+    /// `AppTimer` cannot be modified, so the timer is cancelled and created again
+    fn reschedule(self, delay: Duration) -> Option<Self> {
+        self.timer.cancel();
+
+        Self::start(delay, self.callback)
+    }
+
+    /// Kernel time, which wraps around after ~23h
+    fn now() -> Duration {
+        Duration::from_millis((unsafe { ke_time() } & KE_TIME_MASK) as u64 * 10)
+    }
+
+    /// Wrapping difference of the ticks, exact as long as less than one wrap period passed
+    fn elapsed(earlier: Duration, later: Duration) -> Duration {
+        let ticks = |time: Duration| (time.as_millis() / 10) as u32;
+        let elapsed = ticks(later).wrapping_sub(ticks(earlier)) & KE_TIME_MASK;
+
+        Duration::from_millis(elapsed as u64 * 10)
+    }
+}